@@ -0,0 +1,70 @@
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use qrcode_segments_optimizer::{Optimizer, Options, QrCodeEcc};
+
+const ROUNDS: u32 = 20;
+const ITERATIONS: u32 = 200;
+
+fn inputs() -> Vec<(&'static str, String)> {
+    vec![
+        ("short url", "https://magiclen.org/path/to/12345".to_string()),
+        ("wifi", "WIFI:T:WPA;S:MAGICLEN-OFFICE;P:0123456789abcdef;;".to_string()),
+        ("mixed text", "Order #2024-0001: 3 × ÄPFEL, 12 BANANAS (total 123.45 EUR)".repeat(4)),
+        (
+            "long url",
+            format!("https://magiclen.org/search?q={}&page=1234567890", "ABC123xyz".repeat(60)),
+        ),
+    ]
+}
+
+// Returns the shortest average time of a call among the rounds, which is the least disturbed by other processes.
+fn measure(mut f: impl FnMut()) -> Duration {
+    // warm up
+    for _ in 0..ITERATIONS {
+        f();
+    }
+
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+
+            for _ in 0..ITERATIONS {
+                f();
+            }
+
+            start.elapsed() / ITERATIONS
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let ecc = QrCodeEcc::Low;
+    let options = Options::new();
+
+    for (name, text) in inputs() {
+        let fresh = measure(|| {
+            black_box(
+                qrcode_segments_optimizer::plan_segments_from_str(black_box(&text), ecc, &options)
+                    .unwrap(),
+            );
+        });
+
+        let mut optimizer = Optimizer::new();
+
+        let reused = measure(|| {
+            black_box(optimizer.plan_segments_from_str(black_box(&text), ecc, &options).unwrap());
+        });
+
+        println!(
+            "{:>12} ({:>4} bytes): {:>10.2?} per plan, {:>10.2?} with a reused optimizer",
+            name,
+            text.len(),
+            fresh,
+            reused
+        );
+    }
+}
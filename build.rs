@@ -0,0 +1,91 @@
+// Generates the compact lookup tables of the kanji and Hanzi modes from the JSON files in the `data` directory, which map each UTF-16 code unit to a 13-bit value or -1.
+
+use std::{env, fmt::Write, fs, path::Path};
+
+const BLOCK_BITS: usize = 6;
+const BLOCK_SIZE: usize = 1 << BLOCK_BITS;
+const NONE: u16 = u16::MAX;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+
+    if env::var_os("CARGO_FEATURE_KANJI").is_some() {
+        generate("kanji", &out_dir);
+    }
+
+    if env::var_os("CARGO_FEATURE_HANZI").is_some() {
+        generate("hanzi", &out_dir);
+    }
+}
+
+fn generate(name: &str, out_dir: &str) {
+    let input = format!("data/unicode_to_qr_{name}.json");
+
+    println!("cargo:rerun-if-changed={input}");
+
+    let json = fs::read_to_string(&input).unwrap();
+
+    let table = json
+        .trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(|s| s.trim().parse::<i16>().unwrap())
+        .collect::<Vec<i16>>();
+
+    assert_eq!(table.len(), 1 << 16, "{input} should have an entry for every UTF-16 code unit");
+
+    // block 0 maps nothing, and identical blocks are shared
+    let mut blocks = vec![[NONE; BLOCK_SIZE]];
+    let mut block_indices = Vec::with_capacity(table.len() / BLOCK_SIZE);
+
+    // 0 is unmapped since U+0000 is never mapped; the last code unit wins if a value is mapped twice
+    let mut chars = vec![0u16; 1 << 13];
+
+    for (i, chunk) in table.chunks(BLOCK_SIZE).enumerate() {
+        let mut block = [NONE; BLOCK_SIZE];
+
+        for (j, &val) in chunk.iter().enumerate() {
+            if val >= 0 {
+                assert!(val < 1 << 13, "{input} has a value which doesn't fit in 13 bits");
+
+                block[j] = val as u16;
+                chars[val as usize] = (i * BLOCK_SIZE + j) as u16;
+            }
+        }
+
+        let index = blocks.iter().position(|b| *b == block).unwrap_or_else(|| {
+            blocks.push(block);
+
+            blocks.len() - 1
+        });
+
+        block_indices.push(index as u16);
+    }
+
+    let mut code = String::new();
+
+    code.push_str("DoubleByteTable {\n    block_indices: [");
+    write_values(&mut code, &block_indices);
+    code.push_str("],\n    blocks: &[\n");
+
+    for block in blocks.iter() {
+        code.push_str("        [");
+        write_values(&mut code, block);
+        code.push_str("],\n");
+    }
+
+    code.push_str("    ],\n    chars: [");
+    write_values(&mut code, &chars);
+    code.push_str("],\n}\n");
+
+    fs::write(Path::new(out_dir).join(format!("{name}_table.rs")), code).unwrap();
+}
+
+fn write_values(code: &mut String, values: &[u16]) {
+    for val in values {
+        write!(code, "{val},").unwrap();
+    }
+}
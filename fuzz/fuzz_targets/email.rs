@@ -0,0 +1,24 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qrcode_generator::QrCodeEcc;
+use qrcode_segments_optimizer::{models::Email, Options};
+
+fuzz_target!(|data: &[u8]| {
+    let email: Email = match core::str::from_utf8(data).ok().and_then(|s| s.parse().ok()) {
+        Some(email) => email,
+        None => return,
+    };
+
+    if let Ok(plan) =
+        qrcode_segments_optimizer::plan_segments_from_email(&email, QrCodeEcc::Low, &Options::new())
+    {
+        let decoded = qrcode_segments_optimizer::decode_segments(plan.segments()).unwrap();
+
+        // only the domain part is uppercased
+        assert_eq!(email.to_string().to_ascii_lowercase(), decoded.to_ascii_lowercase());
+        assert!(decoded.parse::<Email>().is_ok());
+    }
+
+    let _ = qrcode_segments_optimizer::make_segments_from_email(&email, QrCodeEcc::High);
+});
@@ -0,0 +1,32 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qrcode_generator::QrCodeEcc;
+use qrcode_segments_optimizer::Options;
+
+fuzz_target!(|data: &[u8]| {
+    let text = match core::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+
+    if let Ok(plan) =
+        qrcode_segments_optimizer::plan_segments_from_str(text, QrCodeEcc::Low, &Options::new())
+    {
+        assert!(plan.data_bits() <= plan.capacity_bits());
+        assert_eq!(
+            Ok(text),
+            qrcode_segments_optimizer::decode_segments(plan.segments()).as_deref()
+        );
+        assert_eq!(
+            Ok(plan.segments()),
+            qrcode_segments_optimizer::parse_data_codewords(
+                &plan.to_data_codewords(),
+                plan.symbol()
+            )
+            .as_deref()
+        );
+    }
+
+    let _ = qrcode_segments_optimizer::make_segments_from_str(text, QrCodeEcc::High);
+});
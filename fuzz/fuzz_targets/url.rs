@@ -0,0 +1,24 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qrcode_generator::QrCodeEcc;
+use qrcode_segments_optimizer::Options;
+use url::Url;
+
+fuzz_target!(|data: &[u8]| {
+    let url = match core::str::from_utf8(data).ok().and_then(|s| Url::parse(s).ok()) {
+        Some(url) => url,
+        None => return,
+    };
+
+    if let Ok(plan) =
+        qrcode_segments_optimizer::plan_segments_from_url(&url, QrCodeEcc::Low, &Options::new())
+    {
+        let decoded = qrcode_segments_optimizer::decode_segments(plan.segments()).unwrap();
+
+        // the uppercased scheme and domain are normalized back by the parser
+        assert_eq!(url, Url::parse(&decoded).unwrap());
+    }
+
+    let _ = qrcode_segments_optimizer::make_segments_from_url(&url, QrCodeEcc::High);
+});
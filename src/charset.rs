@@ -0,0 +1,107 @@
+use core::ops::BitOr;
+
+/// A character set which byte-mode data can be encoded in, declared by an ECI designator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Charset {
+    /// ISO-8859-1 (ECI 3). Every character is one byte, but only U+0000 to U+00FF are supported.
+    Iso8859_1,
+    /// Shift_JIS (ECI 20). Supports ASCII (except `\` and `~`), half-width katakana and the characters of the Kanji mode. Text can only be encoded in or decoded from it with the `kanji` feature.
+    ShiftJis,
+    /// UTF-8 (ECI 26).
+    Utf8,
+}
+
+impl Charset {
+    /// The ECI assignment value of this character set.
+    #[inline]
+    pub const fn eci_assignment(self) -> u32 {
+        match self {
+            Charset::Iso8859_1 => 3,
+            Charset::ShiftJis => 20,
+            Charset::Utf8 => 26,
+        }
+    }
+
+    /// Returns the character set of the given ECI assignment value, if it is one of the variants.
+    #[inline]
+    pub const fn from_eci_assignment(assignment: u32) -> Option<Charset> {
+        match assignment {
+            3 => Some(Charset::Iso8859_1),
+            20 => Some(Charset::ShiftJis),
+            26 => Some(Charset::Utf8),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    const fn flag(self) -> u8 {
+        match self {
+            Charset::Iso8859_1 => 0b001,
+            Charset::ShiftJis => 0b010,
+            Charset::Utf8 => 0b100,
+        }
+    }
+}
+
+/// A set of character sets which byte-mode data can be encoded in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Charsets(u8);
+
+impl Charsets {
+    /// No character set. Only ASCII characters can be encoded in byte mode without an ECI designator.
+    pub const EMPTY: Charsets = Charsets(0);
+    /// ISO-8859-1 only.
+    pub const ISO_8859_1: Charsets = Charsets(Charset::Iso8859_1.flag());
+    /// Shift_JIS only. Without the `kanji` feature, no text can be encoded in it.
+    pub const SHIFT_JIS: Charsets = Charsets(Charset::ShiftJis.flag());
+    /// UTF-8 only.
+    pub const UTF_8: Charsets = Charsets(Charset::Utf8.flag());
+
+    /// All of the character sets which this build supports.
+    #[inline]
+    pub const fn all() -> Charsets {
+        #[cfg(feature = "kanji")]
+        {
+            Charsets(Charsets::ISO_8859_1.0 | Charsets::SHIFT_JIS.0 | Charsets::UTF_8.0)
+        }
+        #[cfg(not(feature = "kanji"))]
+        {
+            Charsets(Charsets::ISO_8859_1.0 | Charsets::UTF_8.0)
+        }
+    }
+
+    /// Whether the character set is in this set.
+    #[inline]
+    pub const fn contains(self, charset: Charset) -> bool {
+        self.0 & charset.flag() != 0
+    }
+
+    /// Add a character set to this set.
+    #[inline]
+    pub const fn with(self, charset: Charset) -> Charsets {
+        Charsets(self.0 | charset.flag())
+    }
+}
+
+impl Default for Charsets {
+    #[inline]
+    fn default() -> Self {
+        Charsets::UTF_8
+    }
+}
+
+impl From<Charset> for Charsets {
+    #[inline]
+    fn from(charset: Charset) -> Self {
+        Charsets(charset.flag())
+    }
+}
+
+impl BitOr for Charsets {
+    type Output = Charsets;
+
+    #[inline]
+    fn bitor(self, rhs: Charsets) -> Charsets {
+        Charsets(self.0 | rhs.0)
+    }
+}
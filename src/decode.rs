@@ -0,0 +1,422 @@
+use alloc::{format, string::String, vec::Vec};
+
+#[cfg(feature = "hanzi")]
+use crate::double_byte_table::HANZI_TABLE;
+#[cfg(feature = "kanji")]
+use crate::double_byte_table::KANJI_TABLE;
+use crate::{
+    qr_segment_advanced::ALPHANUMERIC_CHARSET, Charset, DecodeError, Segment, SegmentMode,
+    SymbolVersion,
+};
+
+/// Decodes the text represented by the given segments.
+///
+/// Byte-mode data is decoded in the character set declared by the ECI designator in effect. Without an ECI designator, it is treated as UTF-8, which is how this crate encodes it by default. Structured Append headers are ignored.
+#[inline]
+pub fn decode_segments(segments: &[Segment]) -> Result<String, DecodeError> {
+    decode_segments_with_implicit_charset(segments, Charset::Utf8)
+}
+
+/// Decodes the text represented by the given segments, where byte-mode data without an ECI designator is in the given character set, such as the segments made with the same `Options::implicit_charset`.
+///
+/// Shift_JIS can only be decoded with the `kanji` feature.
+pub fn decode_segments_with_implicit_charset(
+    segments: &[Segment],
+    implicit_charset: Charset,
+) -> Result<String, DecodeError> {
+    let mut text = String::new();
+    let mut charset = None;
+    let mut pending = Vec::new();
+
+    for segment in segments {
+        match segment.mode() {
+            SegmentMode::Byte => {
+                for (_, val) in group_values(segment)? {
+                    pending.push(val as u8);
+                }
+            },
+            SegmentMode::Eci => {
+                let assignment = eci_assignment(segment)?;
+
+                decode_bytes(&pending, charset, implicit_charset, &mut text)?;
+                pending.clear();
+
+                // Shift_JIS can only be decoded with the kanji feature
+                charset = Some(
+                    Charset::from_eci_assignment(assignment)
+                        .filter(|&charset| cfg!(feature = "kanji") || charset != Charset::ShiftJis)
+                        .ok_or(DecodeError::UnsupportedEci {
+                            assignment,
+                        })?,
+                );
+            },
+            SegmentMode::StructuredAppend => (),
+            _ => {
+                decode_bytes(&pending, charset, implicit_charset, &mut text)?;
+                pending.clear();
+
+                decode_chars(segment, &mut text)?;
+            },
+        }
+    }
+
+    decode_bytes(&pending, charset, implicit_charset, &mut text)?;
+
+    Ok(text)
+}
+
+/// Decodes the raw data represented by the given segments.
+///
+/// Byte-mode data is returned as it is, and the characters of the kanji mode (and the Hanzi mode) are returned as their Shift_JIS (and GB 2312) bytes. ECI designators and Structured Append headers are ignored.
+pub fn decode_segments_to_bytes(segments: &[Segment]) -> Result<Vec<u8>, DecodeError> {
+    let mut data = Vec::new();
+
+    for segment in segments {
+        match segment.mode() {
+            SegmentMode::Numeric | SegmentMode::Alphanumeric => {
+                let mut text = String::new();
+
+                decode_chars(segment, &mut text)?;
+
+                data.extend_from_slice(text.as_bytes());
+            },
+            SegmentMode::Byte => {
+                data.extend(group_values(segment)?.into_iter().map(|(_, val)| val as u8));
+            },
+            SegmentMode::Kanji => {
+                for (_, val) in group_values(segment)? {
+                    // reverse the compaction of the kanji mode
+                    let (msb, lsb) = (val / 0xC0, val % 0xC0);
+                    let sjis = ((msb << 8) | lsb) + if msb <= 0x1E { 0x8140 } else { 0xC140 };
+
+                    data.extend_from_slice(&(sjis as u16).to_be_bytes());
+                }
+            },
+            SegmentMode::Hanzi => {
+                for (_, val) in group_values(segment)? {
+                    // reverse the compaction of the Hanzi mode
+                    let (msb, lsb) = (val / 0x60, val % 0x60);
+                    let gb = ((msb << 8) | lsb) + if msb <= 0x09 { 0xA1A1 } else { 0xA6A1 };
+
+                    data.extend_from_slice(&(gb as u16).to_be_bytes());
+                }
+            },
+            SegmentMode::Eci => {
+                eci_assignment(segment)?;
+            },
+            SegmentMode::StructuredAppend => (),
+        }
+    }
+
+    Ok(data)
+}
+
+/// Parses the segments from the data bitstream of the given symbol, such as the bits of `SegmentPlan::to_data_codewords`.
+///
+/// Parsing stops at the terminator, or at the end of the bitstream if the terminator is truncated.
+pub fn parse_data_bits(bits: &[bool], symbol: SymbolVersion) -> Result<Vec<Segment>, DecodeError> {
+    let mut segments = Vec::new();
+    let mut reader = BitReader {
+        bits,
+        pos: 0,
+    };
+
+    loop {
+        let terminator_bits = symbol.terminator_bits().min(reader.remaining());
+
+        if reader.peek(terminator_bits).iter().all(|&b| !b) {
+            break;
+        }
+
+        let indicator = reader.read(usize::from(symbol.mode_indicator_bits()))?;
+
+        let mode =
+            symbol.mode_from_indicator(indicator).ok_or(DecodeError::InvalidModeIndicator {
+                indicator,
+            })?;
+
+        let segment = match mode {
+            SegmentMode::Eci => {
+                let len = match reader.peek(8).iter().take_while(|&&b| b).count() {
+                    _ if reader.remaining() < 8 => return Err(DecodeError::UnexpectedEnd),
+                    0 => 8,
+                    1 => 16,
+                    2 => 24,
+                    _ => {
+                        return Err(DecodeError::InvalidData {
+                            mode,
+                        })
+                    },
+                };
+
+                let segment = Segment::new(mode, 0, reader.take(len)?.to_vec());
+
+                eci_assignment(&segment)?;
+
+                segment
+            },
+            SegmentMode::StructuredAppend => Segment::new(mode, 0, reader.take(16)?.to_vec()),
+            _ => {
+                if mode.num_subset_bits() > 0
+                    && reader.read(usize::from(mode.num_subset_bits()))? != GB2312_SUBSET
+                {
+                    return Err(DecodeError::InvalidData {
+                        mode,
+                    });
+                }
+
+                // the mode indicator has been validated, so the mode is supported by the symbol
+                let num_chars = reader
+                    .read(usize::from(symbol.num_char_count_bits(mode).unwrap_or(0)))?
+                    as usize;
+
+                let (group_len, group_bits) = group_layout(mode);
+                let len = num_chars / group_len * group_bits[group_len]
+                    + group_bits[num_chars % group_len];
+
+                Segment::new(mode, num_chars, reader.take(len)?.to_vec())
+            },
+        };
+
+        segments.push(segment);
+    }
+
+    Ok(segments)
+}
+
+/// Parses the segments from the data codewords of the given symbol, such as the result of `SegmentPlan::to_data_codewords`. See `parse_data_bits`.
+#[inline]
+pub fn parse_data_codewords(
+    codewords: &[u8],
+    symbol: SymbolVersion,
+) -> Result<Vec<Segment>, DecodeError> {
+    let bits = codewords
+        .iter()
+        .flat_map(|&codeword| (0..8).rev().map(move |i| (codeword >> i) & 1 != 0))
+        .collect::<Vec<bool>>();
+
+    parse_data_bits(&bits, symbol)
+}
+
+// The subset indicator of GB 2312 in the Hanzi mode.
+const GB2312_SUBSET: u32 = 1;
+
+/// Reads bits from a bitstream, most significant bit first.
+struct BitReader<'a> {
+    bits: &'a [bool],
+    pos:  usize,
+}
+
+impl<'a> BitReader<'a> {
+    #[inline]
+    fn remaining(&self) -> usize {
+        self.bits.len() - self.pos
+    }
+
+    #[inline]
+    fn peek(&self, len: usize) -> &'a [bool] {
+        &self.bits[self.pos..(self.pos + len).min(self.bits.len())]
+    }
+
+    #[inline]
+    fn take(&mut self, len: usize) -> Result<&'a [bool], DecodeError> {
+        if len > self.remaining() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+
+        let bits = self.peek(len);
+
+        self.pos += len;
+
+        Ok(bits)
+    }
+
+    #[inline]
+    fn read(&mut self, len: usize) -> Result<u32, DecodeError> {
+        self.take(len).map(to_value)
+    }
+}
+
+#[inline]
+fn to_value(bits: &[bool]) -> u32 {
+    bits.iter().fold(0, |val, &b| (val << 1) | u32::from(b))
+}
+
+/// Returns how many characters a group in the given mode holds at most, and the bit widths of the groups indexed by their numbers of characters.
+#[inline]
+fn group_layout(mode: SegmentMode) -> (usize, &'static [usize]) {
+    match mode {
+        SegmentMode::Numeric => (3, &[0, 4, 7, 10]),
+        SegmentMode::Alphanumeric => (2, &[0, 6, 11]),
+        SegmentMode::Byte => (1, &[0, 8]),
+        _ => (1, &[0, 13]),
+    }
+}
+
+/// Splits the data bits of a segment with a character count into the numbers of characters and the values of its groups.
+fn group_values(segment: &Segment) -> Result<Vec<(usize, u32)>, DecodeError> {
+    let mode = segment.mode();
+    let (group_len, group_bits) = group_layout(mode);
+    let num_chars = segment.num_chars();
+    let data = segment.data();
+
+    if data.len()
+        != num_chars / group_len * group_bits[group_len] + group_bits[num_chars % group_len]
+    {
+        return Err(DecodeError::InvalidData {
+            mode,
+        });
+    }
+
+    let mut values = Vec::with_capacity((num_chars + group_len - 1) / group_len);
+    let mut pos = 0;
+
+    for i in (0..num_chars).step_by(group_len) {
+        let k = group_len.min(num_chars - i);
+        let bits = group_bits[k];
+
+        values.push((k, to_value(&data[pos..pos + bits])));
+
+        pos += bits;
+    }
+
+    Ok(values)
+}
+
+/// Returns the assignment value of an ECI designator.
+fn eci_assignment(segment: &Segment) -> Result<u32, DecodeError> {
+    let data = segment.data();
+    let val = to_value(data);
+
+    let assignment = match data.len() {
+        8 if val >> 7 == 0 => val,
+        16 if val >> 14 == 0b10 => val & 0x3FFF,
+        24 if val >> 21 == 0b110 => val & 0x1F_FFFF,
+        _ => u32::MAX,
+    };
+
+    if assignment < 1_000_000 {
+        Ok(assignment)
+    } else {
+        Err(DecodeError::InvalidData {
+            mode: SegmentMode::Eci
+        })
+    }
+}
+
+/// Decodes the characters of a segment in the numeric, alphanumeric, kanji or Hanzi mode.
+fn decode_chars(segment: &Segment, text: &mut String) -> Result<(), DecodeError> {
+    let mode = segment.mode();
+    let invalid = DecodeError::InvalidData {
+        mode,
+    };
+
+    for (k, val) in group_values(segment)? {
+        match mode {
+            SegmentMode::Numeric => {
+                if val >= 10u32.pow(k as u32) {
+                    return Err(invalid);
+                }
+
+                text.push_str(&format!("{:0k$}", val, k = k));
+            },
+            SegmentMode::Alphanumeric => {
+                let val = val as usize;
+
+                if k == 2 {
+                    if val >= 45 * 45 {
+                        return Err(invalid);
+                    }
+
+                    text.push(ALPHANUMERIC_CHARSET[val / 45]);
+                    text.push(ALPHANUMERIC_CHARSET[val % 45]);
+                } else {
+                    text.push(*ALPHANUMERIC_CHARSET.get(val).ok_or(invalid.clone())?);
+                }
+            },
+            #[cfg(feature = "kanji")]
+            SegmentMode::Kanji => {
+                text.push(KANJI_TABLE.char(val as u16).ok_or(invalid.clone())?);
+            },
+            #[cfg(feature = "hanzi")]
+            SegmentMode::Hanzi => {
+                text.push(HANZI_TABLE.char(val as u16).ok_or(invalid.clone())?);
+            },
+            _ => {
+                return Err(DecodeError::UnsupportedMode {
+                    mode,
+                })
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes byte-mode data in the given character set, or in the implicit one if no ECI designator is in effect.
+fn decode_bytes(
+    bytes: &[u8],
+    charset: Option<Charset>,
+    implicit_charset: Charset,
+    text: &mut String,
+) -> Result<(), DecodeError> {
+    if bytes.is_empty() {
+        return Ok(());
+    }
+
+    let charset = charset.unwrap_or(implicit_charset);
+
+    let invalid = DecodeError::InvalidEncoding {
+        charset,
+    };
+
+    match charset {
+        Charset::Utf8 => {
+            text.push_str(core::str::from_utf8(bytes).map_err(|_| invalid)?);
+        },
+        Charset::Iso8859_1 => {
+            text.extend(bytes.iter().map(|&b| char::from(b)));
+        },
+        #[cfg(feature = "kanji")]
+        Charset::ShiftJis => {
+            let mut iter = bytes.iter().copied();
+
+            while let Some(b) = iter.next() {
+                let c = match b {
+                    // JIS X 0201 replaces them with the yen sign and the overline
+                    0x5C => '\u{A5}',
+                    0x7E => '\u{203E}',
+                    0x00..=0x7F => char::from(b),
+                    // half-width katakana
+                    0xA1..=0xDF => {
+                        char::from_u32(u32::from(b) - 0xA1 + 0xFF61).ok_or(invalid.clone())?
+                    },
+                    0x81..=0x9F | 0xE0..=0xEB => {
+                        let trail = iter.next().ok_or(invalid.clone())?;
+
+                        if !matches!(trail, 0x40..=0xFC) || trail == 0x7F {
+                            return Err(invalid);
+                        }
+
+                        // the compaction of the kanji mode
+                        let sjis = u16::from_be_bytes([b, trail]);
+                        let d = sjis - if b <= 0x9F { 0x8140 } else { 0xC140 };
+
+                        KANJI_TABLE.char((d >> 8) * 0xC0 + (d & 0xFF)).ok_or(invalid.clone())?
+                    },
+                    _ => return Err(invalid),
+                };
+
+                text.push(c);
+            }
+        },
+        #[cfg(not(feature = "kanji"))]
+        Charset::ShiftJis => {
+            return Err(DecodeError::UnsupportedEci {
+                assignment: Charset::ShiftJis.eci_assignment(),
+            })
+        },
+    }
+
+    Ok(())
+}
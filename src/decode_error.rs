@@ -0,0 +1,54 @@
+use core::fmt::{self, Display, Formatter};
+#[cfg(feature = "std")]
+use std::error::Error;
+
+use crate::{Charset, SegmentMode};
+
+/// Errors when decoding QR code segments or data bitstreams.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The bitstream ends in the middle of a segment.
+    UnexpectedEnd,
+    /// The mode indicator is not one of the modes which the symbol supports.
+    InvalidModeIndicator { indicator: u32 },
+    /// The mode is not supported with the enabled features, such as the kanji mode without the `kanji` feature.
+    UnsupportedMode { mode: SegmentMode },
+    /// The data bits of a segment do not match its mode and its character count.
+    InvalidData { mode: SegmentMode },
+    /// The ECI designator declares a character set which is not supported.
+    UnsupportedEci { assignment: u32 },
+    /// The byte-mode data is not valid in the character set in effect, which is the implicit one if no ECI designator is in effect.
+    InvalidEncoding { charset: Charset },
+}
+
+impl Display for DecodeError {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            DecodeError::UnexpectedEnd => {
+                f.write_str("the bitstream ends in the middle of a segment")
+            },
+            DecodeError::InvalidModeIndicator {
+                indicator,
+            } => f.write_fmt(format_args!("the mode indicator {:#b} is invalid", indicator)),
+            DecodeError::UnsupportedMode {
+                mode,
+            } => f.write_fmt(format_args!("the {:?} mode is not supported", mode)),
+            DecodeError::InvalidData {
+                mode,
+            } => f.write_fmt(format_args!("a segment in the {:?} mode has invalid data", mode)),
+            DecodeError::UnsupportedEci {
+                assignment,
+            } => f.write_fmt(format_args!(
+                "the ECI assignment value {} is not supported",
+                assignment
+            )),
+            DecodeError::InvalidEncoding {
+                charset,
+            } => f.write_fmt(format_args!("the byte-mode data is not valid {:?}", charset)),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for DecodeError {}
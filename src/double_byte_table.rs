@@ -0,0 +1,56 @@
+/// The number of bits of the code unit which select the entry in a block.
+const BLOCK_BITS: usize = 6;
+
+/// The value of the code units which aren't mapped.
+const NONE: u16 = u16::MAX;
+
+/// A two-level table mapping UTF-16 code units to the 13-bit values of the kanji or Hanzi mode, along with the reverse mapping. It is generated by the build script from a JSON file in the `data` directory.
+pub(crate) struct DoubleByteTable {
+    /// The index in `blocks` of every 64 code units.
+    block_indices: [u16; 1 << (16 - BLOCK_BITS)],
+    /// The distinct blocks of 13-bit values, where the first one maps nothing.
+    blocks:        &'static [[u16; 1 << BLOCK_BITS]],
+    /// The code unit of every 13-bit value. Unmapped values are 0, since U+0000 is never mapped.
+    chars:         [u16; 1 << 13],
+}
+
+#[cfg(feature = "kanji")]
+pub(crate) static KANJI_TABLE: DoubleByteTable =
+    include!(concat!(env!("OUT_DIR"), "/kanji_table.rs"));
+
+#[cfg(feature = "hanzi")]
+pub(crate) static HANZI_TABLE: DoubleByteTable =
+    include!(concat!(env!("OUT_DIR"), "/hanzi_table.rs"));
+
+impl DoubleByteTable {
+    /// Returns the 13-bit value of the given character, if any.
+    #[inline]
+    pub(crate) fn value(&self, c: char) -> Option<u16> {
+        let c = c as usize;
+
+        if c >= 1 << 16 {
+            return None;
+        }
+
+        let block = self.blocks.get(usize::from(self.block_indices[c >> BLOCK_BITS]))?;
+        let val = block[c & ((1 << BLOCK_BITS) - 1)];
+
+        if val != NONE {
+            Some(val)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the character of the given 13-bit value, if any.
+    #[inline]
+    pub(crate) fn char(&self, val: u16) -> Option<char> {
+        let c = *self.chars.get(usize::from(val))?;
+
+        if c != 0 {
+            char::from_u32(u32::from(c))
+        } else {
+            None
+        }
+    }
+}
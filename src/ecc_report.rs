@@ -0,0 +1,43 @@
+use alloc::vec::Vec;
+
+use crate::{QrCodeEcc, SegmentPlan};
+
+/// The smallest symbol which can hold the same data at each of the four error correction levels.
+#[derive(Debug, Clone)]
+pub struct EccReport {
+    pub(crate) plans: [Option<SegmentPlan>; 4],
+}
+
+impl EccReport {
+    /// The plan at the given error correction level. Returns `None` if the data does not fit any symbol at the level.
+    #[inline]
+    pub fn plan(&self, ecc: QrCodeEcc) -> Option<&SegmentPlan> {
+        self.plans[ecc as usize].as_ref()
+    }
+
+    /// The plans from `Low` to `High`, along with their error correction levels.
+    #[inline]
+    pub fn plans(&self) -> impl Iterator<Item = (QrCodeEcc, Option<&SegmentPlan>)> {
+        [QrCodeEcc::Low, QrCodeEcc::Medium, QrCodeEcc::Quartile, QrCodeEcc::High]
+            .into_iter()
+            .zip(self.plans.iter().map(Option::as_ref))
+    }
+
+    /// The Pareto-optimal plans, from the smallest symbol. For each chosen symbol, only the plan at the highest error correction level is kept, since it costs nothing more than the lower levels.
+    pub fn pareto(&self) -> Vec<&SegmentPlan> {
+        let mut result: Vec<&SegmentPlan> = Vec::with_capacity(4);
+
+        for plan in self.plans.iter().flatten() {
+            // a higher level never needs a smaller symbol, so only the last one can be dominated
+            if let Some(last) = result.last() {
+                if last.symbol() == plan.symbol() {
+                    result.pop();
+                }
+            }
+
+            result.push(plan);
+        }
+
+        result
+    }
+}
@@ -0,0 +1,14 @@
+// The extended grapheme cluster boundaries of UAX #29.
+
+use alloc::vec::Vec;
+use core::iter;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Returns the byte offsets of the characters which start a grapheme cluster, followed by the length of the text. Breaking the text at them never splits a grapheme cluster.
+pub(crate) fn grapheme_boundaries(text: &str) -> Vec<usize> {
+    iter::once(0)
+        .chain(text.grapheme_indices(true).skip(1).map(|(i, _)| i))
+        .chain(iter::once(text.len()).filter(|&len| len > 0))
+        .collect()
+}
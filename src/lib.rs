@@ -6,6 +6,8 @@ This library is used for optimizing the QR code segments.
 ## Examples
 
 ```rust
+# #[cfg(feature = "url")]
+# {
 use qrcode_generator::QrCodeEcc;
 use url::Url;
 
@@ -25,36 +27,447 @@ let optimized_matrix = qrcode_generator::to_matrix_from_segments(
 .unwrap();
 
 assert!(optimized_matrix.len() < naive_matrix.len());
+# }
 ```
+
+## Features
+
+The URL and email front-ends are enabled by the default `url` and `email` features, and can be turned off to drop the `url`, `validators` and `cow-utils` dependencies. The default `std` feature depends on the `qrcode-generator` crate. It adds the functions which return its `QrSegment`s, converts `OptimizeError` into its `QRCodeError`, and implements `std::error::Error` for the error types.
+
+Without the `std` feature, this crate supports `no_std` targets with `alloc`. `QrCodeEcc` and `Version` are then defined by this crate instead of being re-exported from `qrcodegen`, and segments are planned and turned into data codewords with `SegmentPlan::to_data_codewords`.
 */
 
+#![no_std]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+mod charset;
+mod decode;
+mod decode_error;
+#[cfg(any(feature = "kanji", feature = "hanzi"))]
+mod double_byte_table;
+mod ecc_report;
+mod grapheme;
+mod marked_text;
+#[cfg(feature = "email")]
 pub mod models;
+mod optimize_error;
+mod optimizer;
+mod options;
 mod qr_segment_advanced;
-
+mod qr_types;
+mod segment;
+mod segment_constraints;
+mod segment_plan;
+mod strategy_comparison;
+mod symbol;
+
+use alloc::vec::Vec;
+#[cfg(any(feature = "url", feature = "email"))]
+use alloc::{borrow::Cow, string::String};
+#[cfg(feature = "url")]
 use core::{fmt::Write, str::from_utf8_unchecked};
-use std::borrow::Cow;
 
+pub use charset::*;
+#[cfg(any(feature = "url", feature = "email"))]
 use cow_utils::CowUtils;
+pub use decode::*;
+pub use decode_error::*;
+pub use ecc_report::*;
+pub use marked_text::*;
+#[cfg(feature = "email")]
 use models::Email;
-use qrcode_generator::{qrcodegen::Version, QRCodeError, QrCodeEcc, QrSegment};
+pub use optimize_error::*;
+pub use optimizer::*;
+pub use options::*;
+pub use qr_types::*;
+#[cfg(feature = "std")]
+use qrcode_generator::{QRCodeError, QrSegment};
+pub use segment::*;
+pub use segment_constraints::*;
+pub use segment_plan::*;
+pub use strategy_comparison::*;
+pub use symbol::*;
+#[cfg(feature = "url")]
 use url::Url;
+#[cfg(feature = "email")]
 use validators::{models::Host, prelude::*};
 
 /// Make segments from a string slice optimally.
+#[cfg(feature = "std")]
 #[inline]
 pub fn make_segments_from_str<S: AsRef<str>>(
     text: S,
     ecc: QrCodeEcc,
 ) -> Result<Vec<QrSegment>, QRCodeError> {
-    let chars: Vec<char> = text.as_ref().chars().collect();
+    Ok(make_segments_from_str_with_options(text, ecc, &Options::new())?)
+}
+
+/// Make segments from a string slice optimally with the given options.
+#[cfg(feature = "std")]
+#[inline]
+pub fn make_segments_from_str_with_options<S: AsRef<str>>(
+    text: S,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<Vec<QrSegment>, OptimizeError> {
+    plan_segments_from_str(text, ecc, &options.for_qr_segments()).map(SegmentPlan::into_qr_segments)
+}
+
+/// Plan segments from a string slice optimally with the given options.
+#[inline]
+pub fn plan_segments_from_str<S: AsRef<str>>(
+    text: S,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<SegmentPlan, OptimizeError> {
+    Optimizer::new().plan_segments_from_str(text.as_ref(), ecc, options)
+}
+
+/// Plan segments from a string slice optimally with the given options, keeping the pinned ranges in their modes and starting a new segment at each forced boundary.
+///
+/// Returns `OptimizeError::InvalidInput` if the constraints are not at the boundaries of characters, contradict each other, or cannot be satisfied in any symbol which can be chosen.
+#[inline]
+pub fn plan_segments_from_str_with_constraints<S: AsRef<str>>(
+    text: S,
+    ecc: QrCodeEcc,
+    options: &Options,
+    constraints: &SegmentConstraints,
+) -> Result<SegmentPlan, OptimizeError> {
+    Optimizer::new().plan_segments_from_str_with_constraints(
+        text.as_ref(),
+        ecc,
+        options,
+        constraints,
+    )
+}
+
+/// Plan segments from a string slice with case-insensitive spans optimally with the given options. The ASCII letters of a case-insensitive span are uppercased if it saves bits, and the rewritten spans are reported along with the plan.
+///
+/// The spans are chosen by a heuristic rather than by trying every subset of them. Every span is uppercased first, which gives the fewest bits with `Strategy::Optimal` since an uppercase letter never costs more bits than a lowercase one. Then the spans are restored one at a time in the order of the text, as long as that does not cost bits. So a span may be rewritten although another subset with fewer rewritten spans needs as few bits, and with `Strategy::AnnexJ`, another subset may need fewer bits.
+///
+/// Returns `OptimizeError::InvalidInput` if a span is not at the boundaries of characters or overlaps another one.
+#[inline]
+pub fn plan_segments_from_marked_text(
+    text: &MarkedText,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<MarkedTextPlan, OptimizeError> {
+    Optimizer::new().plan_segments_from_marked_text(text, ecc, options)
+}
+
+/// Plan segments from a string slice optimally in the given symbol with the given options. The version range and the target in the options are ignored.
+///
+/// Returns `OptimizeError::DataTooLong` or `OptimizeError::SegmentTooLong` if the text does not fit the symbol at the error correction level.
+#[inline]
+pub fn plan_segments_from_str_for_symbol<S: AsRef<str>>(
+    text: S,
+    symbol: SymbolVersion,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<SegmentPlan, OptimizeError> {
+    Optimizer::new().plan_segments_from_str_for_symbol(text.as_ref(), symbol, ecc, options)
+}
+
+/// Find the longest prefix of a string slice which fits the given symbol, and plan segments for it optimally with the given options. The version range and the target in the options are ignored.
+///
+/// The prefix never ends in the middle of a grapheme cluster, so a character is not separated from its combining marks, and an emoji sequence or a flag is not broken. Returns `OptimizeError::UnsupportedEcc` if the symbol does not support the error correction level.
+pub fn truncate_str_to_fit<'a>(
+    text: &'a str,
+    symbol: SymbolVersion,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<(&'a str, SegmentPlan), OptimizeError> {
+    let (len, plan) = qr_segment_advanced::make_longest_prefix_for_symbol(
+        &mut Optimizer::new(),
+        text,
+        &grapheme::grapheme_boundaries(text),
+        symbol,
+        ecc,
+        options,
+    )?;
+
+    Ok((&text[..len], plan))
+}
+
+/// Plan segments from a string slice optimally at each of the four error correction levels with the given options.
+///
+/// The optimal segments are computed once for each group of symbols which share the segment headers, and reused across the levels. `Options::boost_ecc` is ignored. A level has no plan if the text is too long for it, but `OptimizeError::InvalidInput` is returned if no symbol which can be chosen can encode the text at all.
+#[inline]
+pub fn plan_ecc_levels_from_str<S: AsRef<str>>(
+    text: S,
+    options: &Options,
+) -> Result<EccReport, OptimizeError> {
+    qr_segment_advanced::make_segments_optimally_for_all_ecc(
+        &mut Optimizer::new(),
+        text.as_ref(),
+        options,
+    )
+    .map(|plans| EccReport {
+        plans,
+    })
+}
+
+/// Plan segments of a sequence of up to 16 QR code symbols from a string slice optimally with the given options, by Structured Append.
+///
+/// The text is split into the fewest symbols of the smallest version in the range, and each symbol starts with a Structured Append header. Segments are optimized within each symbol. The target in the options is ignored because only QR code symbols support Structured Append.
+///
+/// The symbols share the error correction level, so `Options::boost_ecc` raises it only to the highest level which every symbol can reach. The parity in the headers is the XOR of the encoded data bytes, which are the byte-mode data in the character sets it is encoded in, the digits and the alphanumeric characters in ASCII, and the characters of the kanji mode (and the Hanzi mode) in Shift_JIS (and GB 2312).
+#[inline]
+pub fn plan_structured_append_from_str<S: AsRef<str>>(
+    text: S,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<Vec<SegmentPlan>, OptimizeError> {
+    qr_segment_advanced::make_structured_append_optimally(
+        &mut Optimizer::new(),
+        text.as_ref(),
+        ecc,
+        options,
+    )
+}
+
+/// Plan segments from a string slice with the given options by both `Strategy::Optimal` and `Strategy::AnnexJ`, to report the bits saved over the mode selection of ISO/IEC 18004 Annex J. The strategy in the options is ignored.
+#[inline]
+pub fn compare_strategies_from_str<S: AsRef<str>>(
+    text: S,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<StrategyComparison, OptimizeError> {
+    let text = text.as_ref();
+    let mut optimizer = Optimizer::new();
+
+    Ok(StrategyComparison {
+        optimal: optimizer.plan_segments_from_str(
+            text,
+            ecc,
+            &options.strategy(Strategy::Optimal),
+        )?,
+        annex_j: optimizer.plan_segments_from_str(
+            text,
+            ecc,
+            &options.strategy(Strategy::AnnexJ),
+        )?,
+    })
+}
+
+/// Make segments from arbitrary binary data optimally.
+#[cfg(feature = "std")]
+#[inline]
+pub fn make_segments_from_bytes<D: AsRef<[u8]>>(
+    data: D,
+    ecc: QrCodeEcc,
+) -> Result<Vec<QrSegment>, QRCodeError> {
+    Ok(make_segments_from_bytes_with_options(data, ecc, &Options::new())?)
+}
+
+/// Make segments from arbitrary binary data optimally with the given options.
+#[cfg(feature = "std")]
+#[inline]
+pub fn make_segments_from_bytes_with_options<D: AsRef<[u8]>>(
+    data: D,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<Vec<QrSegment>, OptimizeError> {
+    plan_segments_from_bytes(data, ecc, &options.for_qr_segments())
+        .map(SegmentPlan::into_qr_segments)
+}
+
+/// Plan segments from arbitrary binary data optimally with the given options.
+///
+/// Runs of bytes which are digits or alphanumeric characters are encoded in the numeric or alphanumeric mode when it saves bits. Other bytes are encoded in the byte mode.
+#[inline]
+pub fn plan_segments_from_bytes<D: AsRef<[u8]>>(
+    data: D,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<SegmentPlan, OptimizeError> {
+    Optimizer::new().plan_segments_from_bytes(data.as_ref(), ecc, options)
+}
+
+/// Plan segments from arbitrary binary data optimally with the given options, keeping the pinned ranges in their modes and starting a new segment at each forced boundary.
+///
+/// See `plan_segments_from_str_with_constraints`.
+#[inline]
+pub fn plan_segments_from_bytes_with_constraints<D: AsRef<[u8]>>(
+    data: D,
+    ecc: QrCodeEcc,
+    options: &Options,
+    constraints: &SegmentConstraints,
+) -> Result<SegmentPlan, OptimizeError> {
+    Optimizer::new().plan_segments_from_bytes_with_constraints(
+        data.as_ref(),
+        ecc,
+        options,
+        constraints,
+    )
+}
 
-    qr_segment_advanced::make_segments_optimally(chars.as_slice(), ecc, Version::MIN, Version::MAX)
-        .ok_or(QRCodeError::DataTooLong)
+/// Plan segments from arbitrary binary data optimally in the given symbol with the given options. The version range and the target in the options are ignored.
+///
+/// Returns `OptimizeError::DataTooLong` or `OptimizeError::SegmentTooLong` if the data does not fit the symbol at the error correction level.
+#[inline]
+pub fn plan_segments_from_bytes_for_symbol<D: AsRef<[u8]>>(
+    data: D,
+    symbol: SymbolVersion,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<SegmentPlan, OptimizeError> {
+    Optimizer::new().plan_segments_from_bytes_for_symbol(data.as_ref(), symbol, ecc, options)
+}
+
+/// Find the longest prefix of arbitrary binary data which fits the given symbol, and plan segments for it optimally with the given options. The version range and the target in the options are ignored.
+///
+/// Returns `OptimizeError::UnsupportedEcc` if the symbol does not support the error correction level.
+pub fn truncate_bytes_to_fit<'a>(
+    data: &'a [u8],
+    symbol: SymbolVersion,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<(&'a [u8], SegmentPlan), OptimizeError> {
+    let prefix_lens: Vec<usize> = (0..=data.len()).collect();
+
+    let (len, plan) = qr_segment_advanced::make_longest_prefix_for_symbol(
+        &mut Optimizer::new(),
+        data,
+        &prefix_lens,
+        symbol,
+        ecc,
+        options,
+    )?;
+
+    Ok((&data[..len], plan))
+}
+
+/// Plan segments from arbitrary binary data optimally at each of the four error correction levels with the given options.
+///
+/// See `plan_ecc_levels_from_str`.
+#[inline]
+pub fn plan_ecc_levels_from_bytes<D: AsRef<[u8]>>(
+    data: D,
+    options: &Options,
+) -> Result<EccReport, OptimizeError> {
+    qr_segment_advanced::make_segments_optimally_for_all_ecc(
+        &mut Optimizer::new(),
+        data.as_ref(),
+        options,
+    )
+    .map(|plans| EccReport {
+        plans,
+    })
+}
+
+/// Plan segments of a sequence of up to 16 QR code symbols from arbitrary binary data optimally with the given options, by Structured Append.
+///
+/// See `plan_structured_append_from_str`.
+#[inline]
+pub fn plan_structured_append_from_bytes<D: AsRef<[u8]>>(
+    data: D,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<Vec<SegmentPlan>, OptimizeError> {
+    qr_segment_advanced::make_structured_append_optimally(
+        &mut Optimizer::new(),
+        data.as_ref(),
+        ecc,
+        options,
+    )
+}
+
+/// Plan segments from arbitrary binary data with the given options by both `Strategy::Optimal` and `Strategy::AnnexJ`. The strategy in the options is ignored.
+///
+/// See `compare_strategies_from_str`.
+#[inline]
+pub fn compare_strategies_from_bytes<D: AsRef<[u8]>>(
+    data: D,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<StrategyComparison, OptimizeError> {
+    let data = data.as_ref();
+    let mut optimizer = Optimizer::new();
+
+    Ok(StrategyComparison {
+        optimal: optimizer.plan_segments_from_bytes(
+            data,
+            ecc,
+            &options.strategy(Strategy::Optimal),
+        )?,
+        annex_j: optimizer.plan_segments_from_bytes(
+            data,
+            ecc,
+            &options.strategy(Strategy::AnnexJ),
+        )?,
+    })
 }
 
 /// Make segments from a url optimally.
+#[cfg(feature = "url")]
 #[inline]
 pub fn make_segments_from_url(url: &Url, ecc: QrCodeEcc) -> Result<Vec<QrSegment>, QRCodeError> {
+    Ok(make_segments_from_url_with_options(url, ecc, &Options::new())?)
+}
+
+/// Make segments from a url optimally with the given options.
+#[cfg(feature = "url")]
+#[inline]
+pub fn make_segments_from_url_with_options(
+    url: &Url,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<Vec<QrSegment>, OptimizeError> {
+    plan_segments_from_url(url, ecc, &options.for_qr_segments()).map(SegmentPlan::into_qr_segments)
+}
+
+/// Plan segments from a url optimally with the given options.
+#[cfg(feature = "url")]
+#[inline]
+pub fn plan_segments_from_url(
+    url: &Url,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<SegmentPlan, OptimizeError> {
+    plan_segments_from_str(url_to_qrcode_str(url), ecc, options)
+}
+
+/// Make segments from an email address optimally.
+#[cfg(feature = "email")]
+#[inline]
+pub fn make_segments_from_email(
+    email: &Email,
+    ecc: QrCodeEcc,
+) -> Result<Vec<QrSegment>, QRCodeError> {
+    Ok(make_segments_from_email_with_options(email, ecc, &Options::new())?)
+}
+
+/// Make segments from an email address optimally with the given options.
+#[cfg(feature = "email")]
+#[inline]
+pub fn make_segments_from_email_with_options(
+    email: &Email,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<Vec<QrSegment>, OptimizeError> {
+    plan_segments_from_email(email, ecc, &options.for_qr_segments())
+        .map(SegmentPlan::into_qr_segments)
+}
+
+/// Plan segments from an email address optimally with the given options.
+#[cfg(feature = "email")]
+#[inline]
+pub fn plan_segments_from_email(
+    email: &Email,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<SegmentPlan, OptimizeError> {
+    plan_segments_from_str(email_to_qrcode_str(email), ecc, options)
+}
+
+/// Rewrite a url to the form which is the most suitable for a QR code.
+/// The scheme and the domain are case-insensitive so they are uppercased.
+#[cfg(feature = "url")]
+fn url_to_qrcode_str(url: &Url) -> Cow<'_, str> {
     let url_str = url.as_str();
 
     let mut qrcode_url: Cow<str> = Cow::Borrowed(url_str);
@@ -67,7 +480,7 @@ pub fn make_segments_from_url(url: &Url, ecc: QrCodeEcc) -> Result<Vec<QrSegment
             s.push_str(scheme.as_str());
             s.push_str(unsafe { from_utf8_unchecked(&url_str.as_bytes()[scheme.len()..]) });
 
-            return make_segments_from_str(s.as_str(), ecc);
+            return Cow::Owned(s);
         }
 
         let mut s = String::with_capacity(url_str.len());
@@ -128,11 +541,14 @@ pub fn make_segments_from_url(url: &Url, ecc: QrCodeEcc) -> Result<Vec<QrSegment
     match qrcode_url {
         Cow::Borrowed(qrcode_url) => {
             // nothing change
-            make_segments_from_str(qrcode_url, ecc)
+            Cow::Borrowed(qrcode_url)
         },
         Cow::Owned(mut s) => {
             if !host_done {
-                s.push_str(url.host_str().unwrap());
+                // a file URL can have no host
+                if let Some(host) = url.host_str() {
+                    s.push_str(host);
+                }
             }
 
             if let Some(port) = url.port() {
@@ -151,17 +567,15 @@ pub fn make_segments_from_url(url: &Url, ecc: QrCodeEcc) -> Result<Vec<QrSegment
                 s.push_str(fragment);
             }
 
-            make_segments_from_str(s.as_str(), ecc)
+            Cow::Owned(s)
         },
     }
 }
 
-/// Make segments from an email address optimally.
-#[inline]
-pub fn make_segments_from_email(
-    email: &Email,
-    ecc: QrCodeEcc,
-) -> Result<Vec<QrSegment>, QRCodeError> {
+/// Rewrite an email address to the form which is the most suitable for a QR code.
+/// The domain part is case-insensitive so it is uppercased.
+#[cfg(feature = "email")]
+fn email_to_qrcode_str(email: &Email) -> Cow<'_, str> {
     if let Host::Domain(domain) = &email.domain_part {
         if let Cow::Owned(domain) = domain.cow_to_ascii_uppercase() {
             let mut s = String::with_capacity(email.local_part.len() + domain.len() + 32);
@@ -204,9 +618,9 @@ pub fn make_segments_from_email(
                 s.push(')');
             }
 
-            return make_segments_from_str(s.as_str(), ecc);
+            return Cow::Owned(s);
         }
     }
 
-    make_segments_from_str(email.to_email_string(), ecc)
+    Cow::Owned(email.to_email_string())
 }
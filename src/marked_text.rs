@@ -0,0 +1,153 @@
+use alloc::{string::String, vec::Vec};
+use core::ops::Range;
+
+use crate::{
+    qr_segment_advanced, OptimizeError, Optimizer, Options, QrCodeEcc, SegmentConstraints,
+    SegmentPlan,
+};
+
+/// A string slice with marked spans, which are byte ranges that must start and end at the boundaries of characters.
+///
+/// ASCII letters in a case-insensitive span (e.g. an order code or a hexadecimal checksum) can be uppercased, so that they can be encoded in the alphanumeric mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarkedText<'a> {
+    pub(crate) text:             &'a str,
+    pub(crate) case_insensitive: Vec<Range<usize>>,
+}
+
+impl<'a> MarkedText<'a> {
+    /// Creates a text without marked spans.
+    #[inline]
+    pub const fn new(text: &'a str) -> Self {
+        MarkedText {
+            text,
+            case_insensitive: Vec::new(),
+        }
+    }
+
+    /// Mark a span as case-insensitive. Case-insensitive spans must not overlap.
+    #[inline]
+    pub fn case_insensitive(mut self, span: Range<usize>) -> Self {
+        self.case_insensitive.push(span);
+
+        self
+    }
+
+    /// The text.
+    #[inline]
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    /// The case-insensitive spans.
+    #[inline]
+    pub fn case_insensitive_spans(&self) -> &[Range<usize>] {
+        self.case_insensitive.as_slice()
+    }
+}
+
+/// The plan of a `MarkedText`, along with the text which the segments actually encode.
+#[derive(Debug, Clone)]
+pub struct MarkedTextPlan {
+    pub(crate) plan:      SegmentPlan,
+    pub(crate) text:      String,
+    pub(crate) rewritten: Vec<Range<usize>>,
+}
+
+impl MarkedTextPlan {
+    /// The plan of the rewritten text.
+    #[inline]
+    pub fn plan(&self) -> &SegmentPlan {
+        &self.plan
+    }
+
+    /// Take the plan of the rewritten text.
+    #[inline]
+    pub fn into_plan(self) -> SegmentPlan {
+        self.plan
+    }
+
+    /// The text which the segments encode, where the rewritten spans are uppercased. Uppercasing ASCII letters does not change the byte offsets.
+    #[inline]
+    pub fn text(&self) -> &str {
+        self.text.as_str()
+    }
+
+    /// The case-insensitive spans which were uppercased, in the order of the text.
+    #[inline]
+    pub fn rewritten_spans(&self) -> &[Range<usize>] {
+        self.rewritten.as_slice()
+    }
+}
+
+/// Returns the plan of the marked text where the case-insensitive spans which save bits are uppercased, chosen greedily.
+pub(crate) fn make_segments_from_marked_text(
+    optimizer: &mut Optimizer,
+    marked_text: &MarkedText,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<MarkedTextPlan, OptimizeError> {
+    let text = marked_text.text;
+
+    let mut spans = marked_text.case_insensitive.clone();
+
+    spans.sort_by_key(|span| span.start);
+
+    for (i, span) in spans.iter().enumerate() {
+        if span.start > span.end
+            || !text.is_char_boundary(span.start)
+            || !text.is_char_boundary(span.end)
+            || spans.get(i + 1).is_some_and(|next| next.start < span.end)
+        {
+            return Err(OptimizeError::InvalidInput);
+        }
+    }
+
+    // only the spans with lowercase ASCII letters can be rewritten
+    spans.retain(|span| text.as_bytes()[span.clone()].iter().any(u8::is_ascii_lowercase));
+
+    let mut rewritten_text = String::from(text);
+
+    for span in spans.iter() {
+        rewritten_text[span.clone()].make_ascii_uppercase();
+    }
+
+    // An uppercase ASCII letter costs no more than a lowercase one in any mode which can encode both,
+    // so uppercasing every span gives the fewest bits with the optimal strategy. Then each span is restored unless that costs more bits,
+    // greedily in the order of the text instead of trying every subset of the spans, so the rewritten spans are not always the fewest ones which give the fewest bits
+    let mut plan = qr_segment_advanced::make_segments_optimally(
+        optimizer,
+        rewritten_text.as_str(),
+        ecc,
+        options,
+        &SegmentConstraints::new(),
+    )?;
+
+    let mut rewritten = Vec::with_capacity(spans.len());
+
+    for span in spans {
+        rewritten_text.replace_range(span.clone(), &text[span.clone()]);
+
+        match qr_segment_advanced::make_segments_for_symbol(
+            optimizer,
+            rewritten_text.as_str(),
+            plan.symbol,
+            ecc,
+            options,
+        ) {
+            Ok(original_plan) if original_plan.data_bits <= plan.data_bits => {
+                plan = original_plan;
+            },
+            _ => {
+                rewritten_text[span.clone()].make_ascii_uppercase();
+                rewritten.push(span);
+            },
+        }
+    }
+
+    Ok(MarkedTextPlan {
+        plan,
+        text: rewritten_text,
+        rewritten,
+    })
+}
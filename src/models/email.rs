@@ -1,3 +1,4 @@
+use alloc::{format, string::String};
 use core::{
     fmt::{self, Display, Formatter},
     str::FromStr,
@@ -0,0 +1,106 @@
+use core::fmt::{self, Display, Formatter};
+#[cfg(feature = "std")]
+use std::{
+    error::Error,
+    io::{self, ErrorKind},
+};
+
+#[cfg(feature = "std")]
+use qrcode_generator::QRCodeError;
+
+use crate::{QrCodeEcc, SegmentMode, SymbolVersion, Version};
+
+/// Errors when optimizing QR code segments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptimizeError {
+    /// The minimum version is greater than the maximum version.
+    InvalidVersionRange { min_version: Version, max_version: Version },
+    /// The supplied data does not fit any symbol which can be chosen. The capacity is of the largest symbol, which has the most capacity. For a Structured Append sequence, the bits are of the whole sequence of 16 symbols of the largest version, including the Structured Append headers.
+    DataTooLong { required_bits: usize, capacity_bits: usize },
+    /// A segment has too many characters to fit its character count field in the largest symbol which can be chosen.
+    SegmentTooLong { mode: SegmentMode, num_chars: usize, max_chars: usize },
+    /// The symbol does not support the error correction level.
+    UnsupportedEcc { symbol: SymbolVersion, ecc: QrCodeEcc },
+    /// The supplied data cannot be encoded in any symbol which can be chosen with the given options, no matter how long it is. For example, M1 only supports digits, and non-ASCII characters need an ECI designator which Micro QR code symbols do not support if ECI designators are required, and a character which none of the allowed modes can encode never fits.
+    InvalidInput,
+    /// The memory which the optimization under the constraints of the cost model needs cannot be allocated. A lower `CostModel::max_segments` or shorter minimum run lengths need less.
+    OutOfMemory,
+}
+
+impl Display for OptimizeError {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            OptimizeError::InvalidVersionRange {
+                min_version,
+                max_version,
+            } => f.write_fmt(format_args!(
+                "the minimum version {} is greater than the maximum version {}",
+                min_version.value(),
+                max_version.value()
+            )),
+            OptimizeError::DataTooLong {
+                required_bits,
+                capacity_bits,
+            } => f.write_fmt(format_args!(
+                "the supplied data needs {} bits, but the largest capacity which can be chosen is \
+                 {} bits",
+                required_bits, capacity_bits
+            )),
+            OptimizeError::SegmentTooLong {
+                mode,
+                num_chars,
+                max_chars,
+            } => f.write_fmt(format_args!(
+                "a segment in the {:?} mode has {} characters, but its character count field can \
+                 only hold {}",
+                mode, num_chars, max_chars
+            )),
+            OptimizeError::UnsupportedEcc {
+                symbol,
+                ecc,
+            } => f.write_fmt(format_args!(
+                "the symbol {} does not support the error correction level {:?}",
+                symbol, ecc
+            )),
+            OptimizeError::InvalidInput => f.write_str(
+                "the supplied data cannot be encoded in any symbol with the given options",
+            ),
+            OptimizeError::OutOfMemory => f.write_str(
+                "the memory needed to optimize under the constraints of the cost model cannot be \
+                 allocated",
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for OptimizeError {}
+
+/// `DataTooLong` and `SegmentTooLong` become `QRCodeError::DataTooLong`. `QRCodeError` has no variant for the other errors, so they are wrapped in `QRCodeError::IOError`, of the `ErrorKind::OutOfMemory` kind for `OutOfMemory` and of the `ErrorKind::InvalidInput` kind for the rest.
+#[cfg(feature = "std")]
+impl From<OptimizeError> for QRCodeError {
+    #[inline]
+    fn from(error: OptimizeError) -> Self {
+        match error {
+            OptimizeError::DataTooLong {
+                ..
+            }
+            | OptimizeError::SegmentTooLong {
+                ..
+            } => QRCodeError::DataTooLong,
+            OptimizeError::OutOfMemory => {
+                QRCodeError::IOError(io::Error::new(ErrorKind::OutOfMemory, error))
+            },
+            OptimizeError::InvalidVersionRange {
+                ..
+            }
+            | OptimizeError::UnsupportedEcc {
+                ..
+            }
+            | OptimizeError::InvalidInput => {
+                QRCodeError::IOError(io::Error::new(ErrorKind::InvalidInput, error))
+            },
+        }
+    }
+}
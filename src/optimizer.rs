@@ -0,0 +1,161 @@
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+use qrcode_generator::QrSegment;
+
+use crate::{
+    marked_text,
+    qr_segment_advanced::{self, NUM_STATES},
+    MarkedText, MarkedTextPlan, OptimizeError, Options, QrCodeEcc, SegmentConstraints, SegmentPlan,
+    SymbolVersion,
+};
+
+/// Reusable working memory for optimizing segments.
+///
+/// The functions of this crate allocate the working memory of the optimization for each input. When a lot of codes are generated, an `Optimizer` can be reused so that only the resulting segments are allocated once its buffers have grown enough.
+#[derive(Debug, Clone, Default)]
+pub struct Optimizer {
+    /// The traceback table of the dynamic programming, which has a previous state for each state of each code point.
+    pub(crate) traceback:             Vec<[u8; NUM_STATES]>,
+    /// The traceback table of the dynamic programming under the constraints of a cost model, which has a previous slot for each slot of each code point.
+    pub(crate) constrained_traceback: Vec<u32>,
+    /// The mode of each code point chosen by a strategy other than the optimal one, which the dynamic programming is restricted to.
+    pub(crate) fixed_modes:           Vec<u8>,
+    /// The optimal state of each code point.
+    pub(crate) states:                Vec<u8>,
+    /// The bytes of a byte-mode segment which are re-encoded in another character set.
+    pub(crate) bytes:                 Vec<u8>,
+}
+
+impl Optimizer {
+    /// Creates an optimizer with empty buffers.
+    #[inline]
+    pub const fn new() -> Self {
+        Optimizer {
+            traceback:             Vec::new(),
+            constrained_traceback: Vec::new(),
+            fixed_modes:           Vec::new(),
+            states:                Vec::new(),
+            bytes:                 Vec::new(),
+        }
+    }
+
+    /// Make segments from a string slice optimally with the given options. See `make_segments_from_str_with_options`.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn make_segments_from_str(
+        &mut self,
+        text: &str,
+        ecc: QrCodeEcc,
+        options: &Options,
+    ) -> Result<Vec<QrSegment>, OptimizeError> {
+        self.plan_segments_from_str(text, ecc, &options.for_qr_segments())
+            .map(SegmentPlan::into_qr_segments)
+    }
+
+    /// Plan segments from a string slice optimally with the given options. See `plan_segments_from_str`.
+    #[inline]
+    pub fn plan_segments_from_str(
+        &mut self,
+        text: &str,
+        ecc: QrCodeEcc,
+        options: &Options,
+    ) -> Result<SegmentPlan, OptimizeError> {
+        qr_segment_advanced::make_segments_optimally(
+            self,
+            text,
+            ecc,
+            options,
+            &SegmentConstraints::new(),
+        )
+    }
+
+    /// Plan segments from a string slice optimally with the given options under the given constraints. See `plan_segments_from_str_with_constraints`.
+    #[inline]
+    pub fn plan_segments_from_str_with_constraints(
+        &mut self,
+        text: &str,
+        ecc: QrCodeEcc,
+        options: &Options,
+        constraints: &SegmentConstraints,
+    ) -> Result<SegmentPlan, OptimizeError> {
+        qr_segment_advanced::make_segments_optimally(self, text, ecc, options, constraints)
+    }
+
+    /// Plan segments from a string slice with case-insensitive spans optimally with the given options. See `plan_segments_from_marked_text`.
+    #[inline]
+    pub fn plan_segments_from_marked_text(
+        &mut self,
+        text: &MarkedText,
+        ecc: QrCodeEcc,
+        options: &Options,
+    ) -> Result<MarkedTextPlan, OptimizeError> {
+        marked_text::make_segments_from_marked_text(self, text, ecc, options)
+    }
+
+    /// Plan segments from a string slice optimally in the given symbol with the given options. See `plan_segments_from_str_for_symbol`.
+    #[inline]
+    pub fn plan_segments_from_str_for_symbol(
+        &mut self,
+        text: &str,
+        symbol: SymbolVersion,
+        ecc: QrCodeEcc,
+        options: &Options,
+    ) -> Result<SegmentPlan, OptimizeError> {
+        qr_segment_advanced::make_segments_for_symbol(self, text, symbol, ecc, options)
+    }
+
+    /// Make segments from arbitrary binary data optimally with the given options. See `make_segments_from_bytes_with_options`.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn make_segments_from_bytes(
+        &mut self,
+        data: &[u8],
+        ecc: QrCodeEcc,
+        options: &Options,
+    ) -> Result<Vec<QrSegment>, OptimizeError> {
+        self.plan_segments_from_bytes(data, ecc, &options.for_qr_segments())
+            .map(SegmentPlan::into_qr_segments)
+    }
+
+    /// Plan segments from arbitrary binary data optimally with the given options. See `plan_segments_from_bytes`.
+    #[inline]
+    pub fn plan_segments_from_bytes(
+        &mut self,
+        data: &[u8],
+        ecc: QrCodeEcc,
+        options: &Options,
+    ) -> Result<SegmentPlan, OptimizeError> {
+        qr_segment_advanced::make_segments_optimally(
+            self,
+            data,
+            ecc,
+            options,
+            &SegmentConstraints::new(),
+        )
+    }
+
+    /// Plan segments from arbitrary binary data optimally with the given options under the given constraints. See `plan_segments_from_bytes_with_constraints`.
+    #[inline]
+    pub fn plan_segments_from_bytes_with_constraints(
+        &mut self,
+        data: &[u8],
+        ecc: QrCodeEcc,
+        options: &Options,
+        constraints: &SegmentConstraints,
+    ) -> Result<SegmentPlan, OptimizeError> {
+        qr_segment_advanced::make_segments_optimally(self, data, ecc, options, constraints)
+    }
+
+    /// Plan segments from arbitrary binary data optimally in the given symbol with the given options. See `plan_segments_from_bytes_for_symbol`.
+    #[inline]
+    pub fn plan_segments_from_bytes_for_symbol(
+        &mut self,
+        data: &[u8],
+        symbol: SymbolVersion,
+        ecc: QrCodeEcc,
+        options: &Options,
+    ) -> Result<SegmentPlan, OptimizeError> {
+        qr_segment_advanced::make_segments_for_symbol(self, data, symbol, ecc, options)
+    }
+}
@@ -0,0 +1,320 @@
+use crate::{Charset, Charsets, SegmentMode, SegmentModes, Version};
+
+/// When to emit ECI designators so that scanners do not decode byte-mode data in another character set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EciPolicy {
+    /// Never emit an ECI designator. Byte-mode data is in the implicit character set without being declared.
+    #[default]
+    Never,
+    /// Emit an ECI designator at the beginning of the data.
+    Prefix,
+    /// Emit an ECI designator before the first byte-mode segment which contains non-ASCII characters, if any.
+    BeforeNonAscii,
+}
+
+/// The kinds of symbols which can be chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Target {
+    /// QR code symbols in the version range.
+    #[default]
+    Qr,
+    /// Micro QR code symbols M1 to M4, and then QR code symbols in the version range. The smallest one which can hold the data is chosen.
+    ///
+    /// Micro QR code symbols do not support ECI designators, so they are skipped if an ECI designator is needed. M1 only supports the numeric mode, M2 also supports the alphanumeric mode, and only M4 supports the `Quartile` error correction level. The `High` level is not supported by any of them.
+    MicroOrQr,
+    /// rMQR code symbols. The one with the smallest area which can hold the data is chosen, and the version range is ignored.
+    ///
+    /// rMQR code symbols only have the M and H error correction levels, so `Low` is raised to M and `Quartile` is raised to H.
+    Rmqr,
+}
+
+/// How the modes of the segments are chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// The segments with the fewest bits (plus the penalties of the cost model), found by dynamic programming.
+    #[default]
+    Optimal,
+    /// The greedy mode selection of ISO/IEC 18004 Annex J, which many other encoders implement, so that their segments can be reproduced. It never uses the Hanzi mode and ignores the cost model. Only the ECI designators are still placed with the fewest bits.
+    AnnexJ,
+}
+
+/// Named bundles of the mode availability, the ECI policy and the segment limits, for scanners which do not handle everything the optimizer can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Profile {
+    /// The modes of ISO/IEC 18004 (all but the Hanzi mode), and an ECI designator before the first byte-mode segment with non-ASCII characters in any supported character set. Byte-mode data without one is ISO-8859-1, which is the default character set of the standard but not what many scanners assume, so it is only used for ASCII.
+    StrictIso,
+    /// No kanji or Hanzi mode, an ECI designator of UTF-8 only when non-ASCII characters need it, and at most 4 data segments.
+    Conservative,
+    /// The kanji mode but no Hanzi mode, no ECI designators, which many domestic Japanese scanners ignore, and byte-mode data in Shift_JIS, which they assume without one. A text with `\` or `~` cannot be encoded, since Shift_JIS has neither. Without the `kanji` feature, there is no kanji mode and byte-mode data is UTF-8.
+    JapaneseDomestic,
+}
+
+impl Profile {
+    /// Returns the default options with this profile applied.
+    #[inline]
+    pub const fn options(self) -> Options {
+        Options::new().profile(self)
+    }
+}
+
+/// How the optimizer weighs the number and the lengths of segments against the number of bits, for scanners which cannot handle many mode switches.
+///
+/// Only data segments are counted. ECI designators and Structured Append headers are not. The optimizer finds the segments with the fewest bits plus penalties among the ones which satisfy the constraints. If the optimal segments without the constraints do not satisfy them, the optimization gets slower and needs more memory, in proportion to `max_segments` times the sum of the minimum run lengths, and `OptimizeError::OutOfMemory` is returned if the memory cannot be allocated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CostModel {
+    /// The extra cost in bits of every data segment after the first one. Default: `0`.
+    pub segment_penalty: usize,
+    /// The maximum number of data segments in a symbol. Default: `None`.
+    pub max_segments:    Option<usize>,
+    min_run_lengths:     [usize; NUM_DATA_MODES],
+}
+
+/// The number of modes which encode data.
+const NUM_DATA_MODES: usize = 5;
+
+/// Returns the index of a mode which encodes data.
+#[inline]
+const fn data_mode_index(mode: SegmentMode) -> Option<usize> {
+    match mode {
+        SegmentMode::Numeric => Some(0),
+        SegmentMode::Alphanumeric => Some(1),
+        SegmentMode::Byte => Some(2),
+        SegmentMode::Kanji => Some(3),
+        SegmentMode::Hanzi => Some(4),
+        SegmentMode::Eci | SegmentMode::StructuredAppend => None,
+    }
+}
+
+impl CostModel {
+    /// Create a cost model which only counts bits.
+    #[inline]
+    pub const fn new() -> Self {
+        CostModel {
+            segment_penalty: 0,
+            max_segments:    None,
+            min_run_lengths: [1; NUM_DATA_MODES],
+        }
+    }
+
+    /// Set the extra cost in bits of every data segment after the first one.
+    #[inline]
+    pub const fn segment_penalty(mut self, segment_penalty: usize) -> Self {
+        self.segment_penalty = segment_penalty;
+
+        self
+    }
+
+    /// Set the maximum number of data segments in a symbol.
+    #[inline]
+    pub const fn max_segments(mut self, max_segments: Option<usize>) -> Self {
+        self.max_segments = max_segments;
+
+        self
+    }
+
+    /// Set the minimum number of characters (bytes for binary data) of a segment in the mode. A segment may be shorter if it is the only one. The modes which don't encode data are ignored. Default: `1`.
+    #[inline]
+    pub const fn with_min_run_length(mut self, mode: SegmentMode, min_run_length: usize) -> Self {
+        if let Some(index) = data_mode_index(mode) {
+            self.min_run_lengths[index] = if min_run_length > 1 { min_run_length } else { 1 };
+        }
+
+        self
+    }
+
+    /// Returns the minimum number of characters of a segment in the mode.
+    #[inline]
+    pub const fn min_run_length(self, mode: SegmentMode) -> usize {
+        match data_mode_index(mode) {
+            Some(index) => self.min_run_lengths[index],
+            None => 1,
+        }
+    }
+
+    /// Whether the segments are constrained beyond the penalty, which needs a larger dynamic programming.
+    #[inline]
+    pub(crate) fn is_constrained(&self) -> bool {
+        self.max_segments.is_some() || self.min_run_lengths.iter().any(|&len| len > 1)
+    }
+}
+
+impl Default for CostModel {
+    #[inline]
+    fn default() -> Self {
+        CostModel::new()
+    }
+}
+
+/// Options for optimizing QR code segments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    /// The minimum QR code version which can be chosen. Default: `Version::MIN`.
+    pub min_version:      Version,
+    /// The maximum QR code version which can be chosen. Default: `Version::MAX`.
+    pub max_version:      Version,
+    /// The kinds of symbols which can be chosen. Default: `Target::Qr`.
+    pub target:           Target,
+    /// Whether to raise the error correction level to the highest one at which the data still fits the chosen symbol, like `boost_ecl` of `QrCode::encode_segments_advanced`. The chosen level is reported by `SegmentPlan::ecc`. Default: `false`.
+    pub boost_ecc:        bool,
+    /// When to emit ECI designators. Default: `EciPolicy::Never`.
+    pub eci:              EciPolicy,
+    /// The character sets which byte-mode data can be encoded in when ECI designators are emitted. The one which needs the fewest bits is chosen for each part of the data. Default: `Charsets::UTF_8`.
+    pub charsets:         Charsets,
+    /// The character set which byte-mode data is encoded in where no ECI designator is in effect, which has to be the one that the scanners assume. Text can only be encoded in Shift_JIS with the `kanji` feature. Default: `Charset::Utf8`.
+    pub implicit_charset: Charset,
+    /// The modes which segments of data can be encoded in. A text which has a character none of them can encode cannot be optimized. The Hanzi mode is not in ISO/IEC 18004 and many readers do not support it, so it has to be added explicitly. Segments in the Hanzi mode cannot be converted to `QrSegment`s, so the functions which return `QrSegment`s never use it. Default: `SegmentModes::all()` without `SegmentMode::Hanzi`.
+    pub modes:            SegmentModes,
+    /// How the number and the lengths of segments are weighed against the number of bits. Default: `CostModel::new()`.
+    pub cost_model:       CostModel,
+    /// How the modes of the segments are chosen. Default: `Strategy::Optimal`.
+    pub strategy:         Strategy,
+}
+
+impl Options {
+    /// Create options which allow all QR code versions.
+    #[inline]
+    pub const fn new() -> Self {
+        Options {
+            min_version:      Version::MIN,
+            max_version:      Version::MAX,
+            target:           Target::Qr,
+            boost_ecc:        false,
+            eci:              EciPolicy::Never,
+            charsets:         Charsets::UTF_8,
+            implicit_charset: Charset::Utf8,
+            modes:            SegmentModes::all().without(SegmentMode::Hanzi),
+            cost_model:       CostModel::new(),
+            strategy:         Strategy::Optimal,
+        }
+    }
+
+    /// Restrict the QR code versions which can be chosen to `min_version..=max_version`.
+    #[inline]
+    pub const fn version_range(mut self, min_version: Version, max_version: Version) -> Self {
+        self.min_version = min_version;
+        self.max_version = max_version;
+
+        self
+    }
+
+    /// Set the kinds of symbols which can be chosen.
+    #[inline]
+    pub const fn target(mut self, target: Target) -> Self {
+        self.target = target;
+
+        self
+    }
+
+    /// Set whether to raise the error correction level when the data still fits the chosen symbol.
+    #[inline]
+    pub const fn boost_ecc(mut self, boost_ecc: bool) -> Self {
+        self.boost_ecc = boost_ecc;
+
+        self
+    }
+
+    /// Set when to emit ECI designators.
+    #[inline]
+    pub const fn eci(mut self, eci: EciPolicy) -> Self {
+        self.eci = eci;
+
+        self
+    }
+
+    /// Set the character sets which byte-mode data can be encoded in when ECI designators are emitted.
+    #[inline]
+    pub const fn charsets(mut self, charsets: Charsets) -> Self {
+        self.charsets = charsets;
+
+        self
+    }
+
+    /// Set the character set which byte-mode data is encoded in where no ECI designator is in effect.
+    #[inline]
+    pub const fn implicit_charset(mut self, implicit_charset: Charset) -> Self {
+        self.implicit_charset = implicit_charset;
+
+        self
+    }
+
+    /// Set the modes which segments of data can be encoded in.
+    #[inline]
+    pub const fn modes(mut self, modes: SegmentModes) -> Self {
+        self.modes = modes;
+
+        self
+    }
+
+    /// Set how the number and the lengths of segments are weighed against the number of bits.
+    #[inline]
+    pub const fn cost_model(mut self, cost_model: CostModel) -> Self {
+        self.cost_model = cost_model;
+
+        self
+    }
+
+    /// Set how the modes of the segments are chosen.
+    #[inline]
+    pub const fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+
+        self
+    }
+
+    /// Set the modes, the ECI policy, the character sets, the implicit character set and the cost model of the profile. The other options are kept.
+    #[inline]
+    pub const fn profile(self, profile: Profile) -> Self {
+        // the Hanzi mode is not in ISO/IEC 18004
+        let iso_modes = SegmentModes::all().without(SegmentMode::Hanzi);
+
+        // domestic Japanese scanners assume Shift_JIS, which can only be encoded with the kanji feature
+        let japanese_charset =
+            if cfg!(feature = "kanji") { Charset::ShiftJis } else { Charset::Utf8 };
+
+        match profile {
+            Profile::StrictIso => self
+                .modes(iso_modes)
+                .eci(EciPolicy::BeforeNonAscii)
+                .charsets(Charsets::all())
+                .implicit_charset(Charset::Iso8859_1)
+                .cost_model(CostModel::new()),
+            Profile::Conservative => self
+                .modes(
+                    SegmentModes::BYTE.with(SegmentMode::Numeric).with(SegmentMode::Alphanumeric),
+                )
+                .eci(EciPolicy::BeforeNonAscii)
+                .charsets(Charsets::UTF_8)
+                .implicit_charset(Charset::Utf8)
+                .cost_model(CostModel::new().max_segments(Some(4))),
+            Profile::JapaneseDomestic => self
+                .modes(iso_modes)
+                .eci(EciPolicy::Never)
+                .charsets(Charsets::UTF_8)
+                .implicit_charset(japanese_charset)
+                .cost_model(CostModel::new()),
+        }
+    }
+
+    /// Returns the options without the symbols and the modes which `QrSegment` does not support.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub(crate) const fn for_qr_segments(self) -> Self {
+        let options = self.target(Target::Qr);
+
+        options.modes(options.modes.without(SegmentMode::Hanzi))
+    }
+}
+
+impl From<Profile> for Options {
+    #[inline]
+    fn from(profile: Profile) -> Self {
+        profile.options()
+    }
+}
+
+impl Default for Options {
+    #[inline]
+    fn default() -> Self {
+        Options::new()
+    }
+}
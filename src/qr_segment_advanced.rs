@@ -1,33 +1,108 @@
 // The algorithm is from https://github.com/nayuki/QR-Code-generator/pull/40/
 
-#[cfg(feature = "kanji")]
-use qrcode_generator::qrcodegen::BitBuffer;
-use qrcode_generator::qrcodegen::{QrCodeEcc, QrSegment, QrSegmentMode, Version};
+use alloc::{vec, vec::Vec};
+use core::{
+    iter::{self, Copied},
+    mem,
+    ops::Range,
+    slice::Iter,
+    str::{from_utf8_unchecked, Chars},
+};
 
+#[cfg(feature = "hanzi")]
+use crate::double_byte_table::HANZI_TABLE;
 #[cfg(feature = "kanji")]
-const MODE_TYPES: [QrSegmentMode; 4] = [
-    QrSegmentMode::Byte,
-    QrSegmentMode::Alphanumeric,
-    QrSegmentMode::Numeric,
-    QrSegmentMode::Kanji,
+use crate::double_byte_table::KANJI_TABLE;
+use crate::{
+    decode_segments_to_bytes, Charset, CostModel, EciPolicy, MicroVersion, OptimizeError,
+    Optimizer, Options, QrCodeEcc, RmqrVersion, Segment, SegmentConstraints, SegmentMode,
+    SegmentPlan, Strategy, SymbolVersion, Target, Version,
+};
+
+const MODE_TYPES: &[SegmentMode] = &[
+    SegmentMode::Byte,
+    SegmentMode::Alphanumeric,
+    SegmentMode::Numeric,
+    #[cfg(feature = "kanji")]
+    SegmentMode::Kanji,
+    #[cfg(feature = "hanzi")]
+    SegmentMode::Hanzi,
 ];
-#[cfg(not(feature = "kanji"))]
-const MODE_TYPES: [QrSegmentMode; 3] =
-    [QrSegmentMode::Byte, QrSegmentMode::Alphanumeric, QrSegmentMode::Numeric];
 
+const NUM_MODES: usize = MODE_TYPES.len();
+
+// The character sets whose ECI designators can be in effect, where `None` means no ECI designator has been emitted.
+// UTF-8 goes first so that it wins the ties.
 #[cfg(feature = "kanji")]
-const NUM_MODES: usize = 4;
+const ECI_STATES: [Option<Charset>; 4] =
+    [None, Some(Charset::Utf8), Some(Charset::Iso8859_1), Some(Charset::ShiftJis)];
 #[cfg(not(feature = "kanji"))]
-const NUM_MODES: usize = 3;
+const ECI_STATES: [Option<Charset>; 3] = [None, Some(Charset::Utf8), Some(Charset::Iso8859_1)];
+
+const NUM_ECI_STATES: usize = ECI_STATES.len();
+
+// Each state of the dynamic programming is a pair of an ECI state and a mode.
+pub(crate) const NUM_STATES: usize = NUM_ECI_STATES * NUM_MODES;
+
+// The number of bits of the assignment value of an ECI segment whose assignment value is less than 128.
+const ECI_ASSIGNMENT_BITS: usize = 8;
+
+// The number of bits of a Structured Append header, including the mode indicator.
+const STRUCTURED_APPEND_HEADER_BITS: usize = 4 + 4 + 4 + 8;
+
+// The maximum number of symbols which can be concatenated by Structured Append.
+const MAX_STRUCTURED_APPEND_SYMBOLS: usize = 16;
+
+// A state which cannot be reached, in the traceback table of the dynamic programming.
+const NO_STATE: u8 = u8::MAX;
+
+// A slot which cannot be reached, in the traceback table of the dynamic programming under the constraints of a cost model.
+const NO_SLOT: u32 = u32::MAX;
+
+// A code point which is not pinned to a mode.
+const NO_MODE: u8 = u8::MAX;
+
+// Whether a new segment may start at a code point, must start there, or must not start there.
+const BOUNDARY_FREE: u8 = 0;
+const BOUNDARY_BREAK: u8 = 1;
+const BOUNDARY_JOIN: u8 = 2;
 
 // The set of all legal characters in alphanumeric mode,
 // where each character value maps to the index in the string.
-static ALPHANUMERIC_CHARSET: [char; 45] = [
+pub(crate) const ALPHANUMERIC_CHARSET: [char; 45] = [
     '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I',
     'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', ' ', '$',
     '%', '*', '+', '-', '.', '/', ':',
 ];
 
+// The modes besides the byte mode which can encode a code point, as bit flags.
+const CLASS_NUMERIC: u8 = 0b0001;
+const CLASS_ALPHANUMERIC: u8 = 0b0010;
+#[cfg(feature = "kanji")]
+const CLASS_KANJI: u8 = 0b0100;
+#[cfg(feature = "hanzi")]
+const CLASS_HANZI: u8 = 0b1000;
+
+// The classes of the ASCII characters, so that they are classified without searching.
+// None of them is in the kanji mode, not even the reverse solidus, which is in the kanji table as the full-width one that scanners would read it back as.
+static ASCII_CLASSES: [u8; 128] = {
+    let mut classes = [0u8; 128];
+
+    let mut i = 0;
+    while i < ALPHANUMERIC_CHARSET.len() {
+        classes[ALPHANUMERIC_CHARSET[i] as usize] |= CLASS_ALPHANUMERIC;
+        i += 1;
+    }
+
+    let mut c = b'0';
+    while c <= b'9' {
+        classes[c as usize] |= CLASS_NUMERIC;
+        c += 1;
+    }
+
+    classes
+};
+
 static ECC_CODEWORDS_PER_BLOCK: [[i8; 41]; 4] = [
     // Version: (note that index 0 is for padding, and is set to an illegal value)
     //0,  1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40    Error correction level
@@ -70,266 +145,1907 @@ static NUM_ERROR_CORRECTION_BLOCKS: [[i8; 41]; 4] = [
     ], // High
 ];
 
-#[cfg(feature = "kanji")]
-pub(crate) static UNICODE_TO_QR_KANJI: [i16; 1 << 16] =
-    include!(concat!(env!("CARGO_MANIFEST_DIR"), "/data/unicode_to_qr_kanji.json"));
+/// A unit of the input data which cannot be split across segments.
+pub(crate) trait CodePoint: Copy {
+    /// Returns the number of bytes needed to encode this code point in byte mode without an ECI designator.
+    fn byte_len(self) -> usize;
 
-/// Returns a list of zero or more segments to represent the specified Unicode text string.
-pub(crate) fn make_segments_optimally(
-    code_points: &[char],
-    ecc: QrCodeEcc,
-    min_version: Version,
-    max_version: Version,
-) -> Option<Vec<QrSegment>> {
-    let min_version = min_version.value();
-    let max_version = max_version.value();
+    /// Writes the bytes of this code point in the given character set (`None` means no ECI designator) into the buffer, and returns the number of the bytes. Returns `None` if the character set does not support this code point.
+    fn encode(self, charset: Option<Charset>, buffer: &mut [u8; 4]) -> Option<usize>;
 
-    // Check arguments
-    // if min_version > max_version {
-    //     return None;
-    // }
+    /// Returns whether this code point is ASCII, which is the same in every character set.
+    fn is_ascii(self) -> bool;
+
+    /// Returns the modes besides the byte mode which can encode this code point, as bit flags.
+    fn classify(self) -> u8;
+
+    /// Returns the 13-bit value of this code point in kanji mode, if any.
+    #[cfg(feature = "kanji")]
+    fn to_kanji(self) -> Option<u16>;
+
+    /// Returns the 13-bit value of this code point in Hanzi mode, if any.
+    #[cfg(feature = "hanzi")]
+    fn to_hanzi(self) -> Option<u16>;
+}
+
+impl CodePoint for char {
+    #[inline]
+    fn byte_len(self) -> usize {
+        self.len_utf8()
+    }
 
-    // Iterate through version numbers, and make tentative segments
-    let mut segs = Vec::new();
+    #[inline]
+    fn encode(self, charset: Option<Charset>, buffer: &mut [u8; 4]) -> Option<usize> {
+        match charset {
+            None | Some(Charset::Utf8) => Some(self.encode_utf8(buffer).len()),
+            Some(Charset::Iso8859_1) => {
+                if (self as u32) <= 0xFF {
+                    buffer[0] = self as u8;
 
-    for version in min_version..=max_version {
-        if version == min_version || version == 10 || version == 27 {
-            segs = make_segments_optimally_at_version(code_points, Version::new(version));
+                    Some(1)
+                } else {
+                    None
+                }
+            },
+            #[cfg(feature = "kanji")]
+            Some(Charset::ShiftJis) => match self {
+                // JIS X 0201 replaces them with the yen sign and the overline
+                '\\' | '~' => None,
+                '\0'..='\x7F' => {
+                    buffer[0] = self as u8;
+
+                    Some(1)
+                },
+                // half-width katakana
+                '\u{FF61}'..='\u{FF9F}' => {
+                    buffer[0] = (self as u32 - 0xFF61 + 0xA1) as u8;
+
+                    Some(1)
+                },
+                _ => {
+                    let val = self.to_kanji()?;
+
+                    // reverse the compaction of the kanji mode
+                    let (msb, lsb) = (val / 0xC0, val % 0xC0);
+                    let sjis = ((msb << 8) | lsb) + if msb <= 0x1E { 0x8140 } else { 0xC140 };
+
+                    buffer[..2].copy_from_slice(&sjis.to_be_bytes());
+
+                    Some(2)
+                },
+            },
+            #[cfg(not(feature = "kanji"))]
+            Some(Charset::ShiftJis) => None,
         }
-        let version = Version::new(version);
+    }
 
-        // Check if the segments fit
-        let data_capacity_bits = get_num_data_codewords(version, ecc) * 8;
-        let data_used_bits = get_total_bits(&segs, version);
+    #[inline]
+    fn is_ascii(self) -> bool {
+        char::is_ascii(&self)
+    }
 
-        if let Some(data_used_bits) = data_used_bits {
-            if data_used_bits <= data_capacity_bits {
-                return Some(segs); // This version number is found to be suitable
-            }
+    #[inline]
+    fn classify(self) -> u8 {
+        if char::is_ascii(&self) {
+            return ASCII_CLASSES[self as usize];
+        }
+
+        #[cfg(feature = "kanji")]
+        let kanji = if self.to_kanji().is_some() { CLASS_KANJI } else { 0 };
+        #[cfg(not(feature = "kanji"))]
+        let kanji = 0;
+
+        #[cfg(feature = "hanzi")]
+        let hanzi = if self.to_hanzi().is_some() { CLASS_HANZI } else { 0 };
+        #[cfg(not(feature = "hanzi"))]
+        let hanzi = 0;
+
+        kanji | hanzi
+    }
+
+    #[cfg(feature = "kanji")]
+    #[inline]
+    fn to_kanji(self) -> Option<u16> {
+        KANJI_TABLE.value(self)
+    }
+
+    #[cfg(feature = "hanzi")]
+    #[inline]
+    fn to_hanzi(self) -> Option<u16> {
+        HANZI_TABLE.value(self)
+    }
+}
+
+impl CodePoint for u8 {
+    #[inline]
+    fn byte_len(self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn encode(self, _charset: Option<Charset>, buffer: &mut [u8; 4]) -> Option<usize> {
+        buffer[0] = self;
+
+        Some(1)
+    }
+
+    #[inline]
+    fn is_ascii(self) -> bool {
+        u8::is_ascii(&self)
+    }
+
+    #[inline]
+    fn classify(self) -> u8 {
+        if u8::is_ascii(&self) {
+            // arbitrary bytes are never in kanji mode
+            ASCII_CLASSES[usize::from(self)] & (CLASS_NUMERIC | CLASS_ALPHANUMERIC)
+        } else {
+            0
         }
     }
 
-    None
+    #[cfg(feature = "kanji")]
+    #[inline]
+    fn to_kanji(self) -> Option<u16> {
+        None
+    }
+
+    #[cfg(feature = "hanzi")]
+    #[inline]
+    fn to_hanzi(self) -> Option<u16> {
+        None
+    }
 }
 
-// Returns a new list of segments that is optimal for the given text at the given version number.
-fn make_segments_optimally_at_version(code_points: &[char], version: Version) -> Vec<QrSegment> {
-    let char_modes = compute_character_modes(code_points, version);
-    split_into_segments(code_points, &char_modes)
+/// The input data, which is sliced by byte offsets at the boundaries of its code points.
+pub(crate) trait Text<'a>: Copy {
+    type CodePoint: CodePoint;
+    type CodePoints: Iterator<Item = Self::CodePoint>;
+
+    /// Returns the length of the data in bytes.
+    fn len(self) -> usize;
+
+    /// Returns the part of the data in the byte range, which must start and end at the boundaries of code points.
+    fn slice(self, range: Range<usize>) -> Self;
+
+    /// Returns the bytes of the data, which are the bytes of its code points encoded without an ECI designator.
+    fn as_bytes(self) -> &'a [u8];
+
+    /// Returns an iterator over the code points of the data.
+    fn code_points(self) -> Self::CodePoints;
 }
 
-// Returns a new array representing the optimal mode per code point based on the given text and version.
-fn compute_character_modes(code_points: &[char], version: Version) -> Vec<QrSegmentMode> {
-    // Segment header sizes, measured in 1/6 bits
-    let mut head_costs = [0usize; NUM_MODES];
+impl<'a> Text<'a> for &'a str {
+    type CodePoint = char;
+    type CodePoints = Chars<'a>;
+
+    #[inline]
+    fn len(self) -> usize {
+        str::len(self)
+    }
 
-    for i in 0..NUM_MODES {
-        head_costs[i] = (4 + num_char_count_bits(MODE_TYPES[i], version) as usize) * 6;
+    #[inline]
+    fn slice(self, range: Range<usize>) -> Self {
+        &self[range]
     }
 
-    // charModes[i][j] represents the mode to encode the code point at index i
-    // such that the final segment ends in modeTypes[j] and the total number of bits is minimized over all possible choices
-    let mut char_modes = vec![[None::<QrSegmentMode>; NUM_MODES]; code_points.len()];
+    #[inline]
+    fn as_bytes(self) -> &'a [u8] {
+        str::as_bytes(self)
+    }
 
-    // At the beginning of each iteration of the loop below,
-    // prevCosts[j] is the exact minimum number of 1/6 bits needed to encode the entire string prefix of length i, and end in modeTypes[j]
-    let mut prev_costs = head_costs;
+    #[inline]
+    fn code_points(self) -> Self::CodePoints {
+        self.chars()
+    }
+}
 
-    // Calculate costs using dynamic programming
-    for i in 0..code_points.len() {
-        let c = code_points[i];
-        let mut cur_costs = [0usize; NUM_MODES];
+impl<'a> Text<'a> for &'a [u8] {
+    type CodePoint = u8;
+    type CodePoints = Copied<Iter<'a, u8>>;
 
-        {
-            // Always extend a byte mode segment
-            cur_costs[0] = prev_costs[0] + c.len_utf8() * 8 * 6;
-            char_modes[i][0] = Some(MODE_TYPES[0]);
+    #[inline]
+    fn len(self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    #[inline]
+    fn slice(self, range: Range<usize>) -> Self {
+        &self[range]
+    }
+
+    #[inline]
+    fn as_bytes(self) -> &'a [u8] {
+        self
+    }
+
+    #[inline]
+    fn code_points(self) -> Self::CodePoints {
+        self.iter().copied()
+    }
+}
+
+/// Returns a plan of zero or more segments to represent the specified text, which satisfy the given constraints.
+pub(crate) fn make_segments_optimally<'a, T: Text<'a>>(
+    optimizer: &mut Optimizer,
+    text: T,
+    ecc: QrCodeEcc,
+    options: &Options,
+    constraints: &SegmentConstraints,
+) -> Result<SegmentPlan, OptimizeError> {
+    let symbols = get_symbols(options)?;
+    let constraints = CodePointConstraints::new(text, constraints)?;
+
+    check_relaxed_fit(optimizer, text, &symbols, ecc, options, &constraints)?;
+
+    // Iterate through symbols from the smallest, and make tentative segments
+    // whenever the segment headers change
+    let mut segs = None;
+    let mut headers = None;
+
+    // The reason why the last symbol, which is the largest one, cannot be chosen
+    let mut error = OptimizeError::InvalidInput;
+
+    for symbol in symbols {
+        let data_capacity_bits = match symbol.num_data_bits(ecc) {
+            Some(bits) => bits,
+            None => {
+                error = OptimizeError::UnsupportedEcc {
+                    symbol,
+                    ecc,
+                };
+
+                continue;
+            },
+        };
+
+        let symbol_headers = get_headers(symbol);
+
+        if headers != Some(symbol_headers) {
+            segs =
+                make_segments_optimally_at_version(optimizer, text, symbol, options, &constraints)?;
+            headers = Some(symbol_headers);
         }
 
-        // Extend a segment if possible
-        if ALPHANUMERIC_CHARSET.contains(&c) {
-            // Is alphanumeric
-            cur_costs[1] = prev_costs[1] + 33; // 5.5 bits per alphanumeric char
-            char_modes[i][1] = Some(MODE_TYPES[1]);
+        let segs = match &segs {
+            Some(segs) => segs,
+            None => {
+                error = OptimizeError::InvalidInput;
+
+                continue;
+            },
+        };
+
+        // Check if the segments fit
+        match check_fit(segs, symbol, data_capacity_bits) {
+            Ok(data_used_bits) => {
+                // This symbol is found to be suitable
+                let mut plan = SegmentPlan {
+                    segments: segs.clone(),
+                    symbol,
+                    ecc: symbol.supported_ecc(ecc).unwrap_or(ecc),
+                    data_bits: data_used_bits,
+                    capacity_bits: data_capacity_bits,
+                    naive_bits: get_naive_bits(text.len(), symbol),
+                };
+
+                if options.boost_ecc {
+                    plan.boost_ecc();
+                }
+
+                return Ok(plan);
+            },
+            Err(err) => error = err,
+        }
+    }
+
+    Err(error)
+}
+
+/// Returns the plans of the specified text at the four error correction levels, from `Low` to `High`. A plan is `None` if the text does not fit any symbol at the level.
+pub(crate) fn make_segments_optimally_for_all_ecc<'a, T: Text<'a>>(
+    optimizer: &mut Optimizer,
+    text: T,
+    options: &Options,
+) -> Result<[Option<SegmentPlan>; 4], OptimizeError> {
+    const ECC_LEVELS: [QrCodeEcc; 4] =
+        [QrCodeEcc::Low, QrCodeEcc::Medium, QrCodeEcc::Quartile, QrCodeEcc::High];
+
+    let symbols = get_symbols(options)?;
+
+    let mut plans = [None, None, None, None];
+
+    // The segments are shared by all levels because they only depend on the segment headers
+    let mut segs = None;
+    let mut headers = None;
+
+    // Whether any symbol can encode the text, no matter how long it is
+    let mut encodable = false;
+
+    for symbol in symbols {
+        if plans.iter().all(Option::is_some) {
+            break;
         }
-        if c.is_ascii_digit() {
-            // Is numeric
-            cur_costs[2] = prev_costs[2] + 20; // 3.33 bits per digit
-            char_modes[i][2] = Some(MODE_TYPES[2]);
+
+        let symbol_headers = get_headers(symbol);
+
+        if headers != Some(symbol_headers) {
+            segs = make_segments_optimally_at_version(
+                optimizer,
+                text,
+                symbol,
+                options,
+                &CodePointConstraints::NONE,
+            )?;
+            headers = Some(symbol_headers);
         }
-        #[cfg(feature = "kanji")]
-        {
-            if is_kanji(c) {
-                cur_costs[3] = prev_costs[3] + 78; // 13 bits per Shift JIS char
-                char_modes[i][3] = Some(MODE_TYPES[3]);
+
+        let segs = match &segs {
+            Some(segs) => segs,
+            None => continue,
+        };
+
+        encodable = true;
+
+        let data_used_bits = match get_total_bits(segs, symbol) {
+            Some(bits) => bits,
+            None => continue,
+        };
+
+        for (plan, ecc) in plans.iter_mut().zip(ECC_LEVELS) {
+            if plan.is_some() {
+                continue;
             }
-        }
 
-        // Start new segment at the end to switch modes
-        for j in 0..NUM_MODES {
-            // To mode
-            for k in 0..NUM_MODES {
-                // From mode
-                let new_cost = (cur_costs[k] + 5) / 6 * 6 + head_costs[j];
-                if char_modes[i][k].is_some()
-                    && (char_modes[i][j].is_none() || new_cost < cur_costs[j])
-                {
-                    cur_costs[j] = new_cost;
-                    char_modes[i][j] = Some(MODE_TYPES[k]);
+            if let Some(data_capacity_bits) = symbol.num_data_bits(ecc) {
+                if data_used_bits <= data_capacity_bits {
+                    *plan = Some(SegmentPlan {
+                        segments: segs.clone(),
+                        symbol,
+                        ecc: symbol.supported_ecc(ecc).unwrap(),
+                        data_bits: data_used_bits,
+                        capacity_bits: data_capacity_bits,
+                        naive_bits: get_naive_bits(text.len(), symbol),
+                    });
                 }
             }
         }
+    }
 
-        prev_costs = cur_costs;
+    if !encodable {
+        return Err(OptimizeError::InvalidInput);
     }
 
-    // Find optimal ending mode
-    let mut cur_mode = None::<QrSegmentMode>;
+    Ok(plans)
+}
 
-    let mut min_cost = 0;
+// Returns the symbols which can be chosen, from the smallest.
+fn get_symbols(options: &Options) -> Result<Vec<SymbolVersion>, OptimizeError> {
+    let (min_version, max_version) = (options.min_version, options.max_version);
+
+    // Check arguments
+    if min_version > max_version {
+        return Err(OptimizeError::InvalidVersionRange {
+            min_version,
+            max_version,
+        });
+    }
+
+    let qr_versions = (min_version.value()..=max_version.value())
+        .map(|version| SymbolVersion::Qr(Version::new(version)));
+
+    let symbols: Vec<SymbolVersion> = match options.target {
+        Target::Qr => qr_versions.collect(),
+        Target::MicroOrQr => (MicroVersion::MIN.value()..=MicroVersion::MAX.value())
+            .map(|version| SymbolVersion::Micro(MicroVersion::new(version)))
+            .chain(qr_versions)
+            .collect(),
+        Target::Rmqr => {
+            let mut symbols: Vec<SymbolVersion> =
+                RmqrVersion::all().map(SymbolVersion::Rmqr).collect();
+
+            // the smallest area goes first, and then the lowest height
+            symbols.sort_by_key(|symbol| (symbol.width() * symbol.height(), symbol.height()));
+
+            symbols
+        },
+    };
+
+    Ok(symbols)
+}
+
+/// Returns a plan of the specified text in the given symbol. The symbols in the options are ignored.
+pub(crate) fn make_segments_for_symbol<'a, T: Text<'a>>(
+    optimizer: &mut Optimizer,
+    text: T,
+    symbol: SymbolVersion,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<SegmentPlan, OptimizeError> {
+    fit_symbol(optimizer, text, symbol, ecc, options)
+}
+
+/// Returns the longest prefix of the specified text which fits the given symbol, along with its plan. The prefix is chosen among the byte lengths `prefix_lens`, which must be sorted in ascending order.
+pub(crate) fn make_longest_prefix_for_symbol<'a, T: Text<'a>>(
+    optimizer: &mut Optimizer,
+    text: T,
+    prefix_lens: &[usize],
+    symbol: SymbolVersion,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<(usize, SegmentPlan), OptimizeError> {
+    // The bits needed by a prefix never decrease as it grows without the constraints, so binary search for the longest one which fits
+    let relaxed_options = relax_options(options);
+    let mut result = None;
+    let mut error = OptimizeError::InvalidInput;
+    let (mut low, mut high) = (0, prefix_lens.len());
 
-    for i in 0..NUM_MODES {
-        if cur_mode.is_none() || prev_costs[i] < min_cost {
-            min_cost = prev_costs[i];
-            cur_mode = Some(MODE_TYPES[i]);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let len = prefix_lens[mid];
+
+        match fit_symbol(optimizer, text.slice(0..len), symbol, ecc, &relaxed_options) {
+            Ok(plan) => {
+                result = Some((len, plan));
+                low = mid + 1;
+            },
+            Err(err) => {
+                error = err;
+                high = mid;
+            },
+        }
+    }
+
+    if relaxed_options == *options {
+        return result.ok_or(error);
+    }
+
+    // Under the constraints, a prefix can need fewer bits than a shorter one, so try the prefixes from the longest one which fits without them
+    for &len in prefix_lens[..low].iter().rev() {
+        match fit_symbol(optimizer, text.slice(0..len), symbol, ecc, options) {
+            Ok(plan) => return Ok((len, plan)),
+            Err(err) => error = err,
         }
     }
 
-    let mut cur_mode = cur_mode.unwrap();
+    Err(error)
+}
+
+// Returns the options without the cost model and with the optimal strategy, with which the bits needed by a text never decrease as it grows, and are never more than the bits needed with the given options.
+// With the given options, a longer text can need fewer bits, when it reaches a minimum run length, when the look-ahead of Annex J sees a longer run, or when it trades the penalty of a segment for fewer bits.
+fn relax_options(options: &Options) -> Options {
+    options.cost_model(CostModel::new()).strategy(Strategy::Optimal)
+}
+
+// Returns a plan of the given text in the given symbol if it fits.
+fn fit_symbol<'a, T: Text<'a>>(
+    optimizer: &mut Optimizer,
+    text: T,
+    symbol: SymbolVersion,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<SegmentPlan, OptimizeError> {
+    let data_capacity_bits = symbol.num_data_bits(ecc).ok_or(OptimizeError::UnsupportedEcc {
+        symbol,
+        ecc,
+    })?;
+
+    let (segs, data_used_bits) =
+        fit_capacity(optimizer, text, symbol, data_capacity_bits, options)?;
+
+    let mut plan = SegmentPlan {
+        segments: segs,
+        symbol,
+        ecc: symbol.supported_ecc(ecc).unwrap_or(ecc),
+        data_bits: data_used_bits,
+        capacity_bits: data_capacity_bits,
+        naive_bits: get_naive_bits(text.len(), symbol),
+    };
+
+    if options.boost_ecc {
+        plan.boost_ecc();
+    }
 
-    let mut result = Vec::with_capacity(char_modes.len());
+    Ok(plan)
+}
 
-    let remaining = result.spare_capacity_mut();
+// Returns the number of bits used by the given segments in the given symbol if they fit the capacity.
+fn check_fit(
+    segs: &[Segment],
+    symbol: SymbolVersion,
+    data_capacity_bits: usize,
+) -> Result<usize, OptimizeError> {
+    let data_used_bits = match get_total_bits(segs, symbol) {
+        Some(bits) => bits,
+        None => {
+            // Find the segment which has too many characters to fit its length field
+            for seg in segs {
+                if let Some(ccbits) = symbol.num_char_count_bits(seg.mode()) {
+                    let max_chars = (1 << ccbits) - 1;
 
-    // Get optimal mode for each code point by tracing backwards
-    for i in (0..char_modes.len()).rev() {
-        for (j, e) in MODE_TYPES.iter().copied().enumerate() {
-            if e == cur_mode {
-                cur_mode = char_modes[i][j].unwrap();
-                remaining[i].write(cur_mode);
-                break;
+                    if seg.num_chars() > max_chars {
+                        return Err(OptimizeError::SegmentTooLong {
+                            mode: seg.mode(),
+                            num_chars: seg.num_chars(),
+                            max_chars,
+                        });
+                    }
+                }
             }
-        }
+
+            return Err(OptimizeError::InvalidInput);
+        },
+    };
+
+    if data_used_bits <= data_capacity_bits {
+        Ok(data_used_bits)
+    } else {
+        Err(OptimizeError::DataTooLong {
+            required_bits: data_used_bits,
+            capacity_bits: data_capacity_bits,
+        })
     }
+}
+
+/// Returns plans of up to 16 QR code symbols of the same version, which hold the specified text in sequence by Structured Append.
+pub(crate) fn make_structured_append_optimally<'a, T: Text<'a>>(
+    optimizer: &mut Optimizer,
+    text: T,
+    ecc: QrCodeEcc,
+    options: &Options,
+) -> Result<Vec<SegmentPlan>, OptimizeError> {
+    let (min_version, max_version) = (options.min_version, options.max_version);
 
-    unsafe {
-        result.set_len(char_modes.len());
+    // Check arguments
+    if min_version > max_version {
+        return Err(OptimizeError::InvalidVersionRange {
+            min_version,
+            max_version,
+        });
     }
 
-    result
+    let mut error = OptimizeError::InvalidInput;
+
+    for version in min_version.value()..=max_version.value() {
+        let symbol = SymbolVersion::Qr(Version::new(version));
+        let data_capacity_bits = get_num_data_codewords(Version::new(version), ecc) * 8;
+
+        let chunks = match split_into_symbols(optimizer, text, symbol, data_capacity_bits, options)
+        {
+            Ok(chunks) => chunks,
+            Err(err) => {
+                error = err;
+
+                continue;
+            },
+        };
+
+        {
+            let total = chunks.len() as u8;
+
+            // The parity is computed over the encoded data of the whole sequence, where byte-mode data is in the character sets it is re-encoded in, and the characters of the kanji mode are their Shift_JIS bytes
+            let parity = chunks
+                .iter()
+                .flat_map(|chunk| decode_segments_to_bytes(&chunk.segments).unwrap())
+                .fold(0u8, |parity, b| parity ^ b);
+
+            let mut plans: Vec<SegmentPlan> = chunks
+                .into_iter()
+                .enumerate()
+                .map(|(index, chunk)| {
+                    let mut segments = Vec::with_capacity(chunk.segments.len() + 1);
+
+                    segments.push(Segment::make_structured_append(index as u8, total, parity));
+                    segments.extend(chunk.segments);
+
+                    SegmentPlan {
+                        segments,
+                        symbol,
+                        ecc,
+                        data_bits: STRUCTURED_APPEND_HEADER_BITS + chunk.data_bits,
+                        capacity_bits: data_capacity_bits,
+                        naive_bits: get_naive_bits(chunk.range.len(), symbol)
+                            .map(|bits| STRUCTURED_APPEND_HEADER_BITS + bits),
+                    }
+                })
+                .collect();
+
+            if options.boost_ecc {
+                // The symbols of a sequence share the level, so it is raised only as far as every symbol can be
+                let boosted_ecc = plans.iter().map(SegmentPlan::highest_ecc).min().unwrap();
+
+                for plan in plans.iter_mut() {
+                    plan.set_ecc(boosted_ecc);
+                }
+            }
+
+            return Ok(plans);
+        }
+    }
+
+    Err(error)
 }
 
-// Returns a new list of segments based on the given text and modes, such that consecutive code points in the same mode are put into the same segment.
-fn split_into_segments(code_points: &[char], char_modes: &[QrSegmentMode]) -> Vec<QrSegment> {
-    let mut result = Vec::new();
+// The part of the text which is put in one of the symbols concatenated by Structured Append.
+struct SymbolChunk {
+    range:     Range<usize>,
+    segments:  Vec<Segment>,
+    data_bits: usize,
+}
 
-    // Accumulate run of modes
-    let mut cur_mode = char_modes[0];
+// Splits the given text into the fewest symbols, up to 16, by taking the longest prefix which fits each symbol. If 16 symbols are not enough, the error reports the bits of the whole sequence.
+fn split_into_symbols<'a, T: Text<'a>>(
+    optimizer: &mut Optimizer,
+    text: T,
+    symbol: SymbolVersion,
+    data_capacity_bits: usize,
+    options: &Options,
+) -> Result<Vec<SymbolChunk>, OptimizeError> {
+    let capacity_bits = data_capacity_bits.saturating_sub(STRUCTURED_APPEND_HEADER_BITS);
 
+    // The byte offsets of the boundaries of the code points, where the text can be split
+    let mut offset = 0;
+    let boundaries: Vec<usize> = iter::once(0)
+        .chain(text.code_points().map(|c| {
+            offset += c.byte_len();
+
+            offset
+        }))
+        .collect();
+
+    let relaxed_options = relax_options(options);
+
+    let mut chunks: Vec<SymbolChunk> = Vec::new();
     let mut start = 0;
 
-    let mut i = 0;
     loop {
-        i += 1;
+        if chunks.len() == MAX_STRUCTURED_APPEND_SYMBOLS {
+            // The rest would need at least one more symbol
+            let rest_bits = make_segments_optimally_at_version(
+                optimizer,
+                text.slice(boundaries[start]..text.len()),
+                symbol,
+                options,
+                &CodePointConstraints::NONE,
+            )?
+            .and_then(|segs| get_total_bits(&segs, symbol))
+            .unwrap_or(usize::MAX);
 
-        if i < code_points.len() && char_modes[i] == cur_mode {
-            continue;
+            return Err(OptimizeError::DataTooLong {
+                required_bits: chunks
+                    .iter()
+                    .map(|chunk| STRUCTURED_APPEND_HEADER_BITS + chunk.data_bits)
+                    .sum::<usize>()
+                    .saturating_add(STRUCTURED_APPEND_HEADER_BITS)
+                    .saturating_add(rest_bits),
+                capacity_bits: data_capacity_bits * MAX_STRUCTURED_APPEND_SYMBOLS,
+            });
         }
 
-        let s = &code_points[start..i];
+        // The bits needed by a range never decrease as it grows without the constraints, so binary search for the longest one which fits
+        let mut end = start;
+        let mut end_fit = (Vec::new(), 0);
+        let mut not_fit_end = boundaries.len();
 
-        match cur_mode {
-            QrSegmentMode::Byte => {
-                let s: String = s.iter().collect();
-                let v = s.into_bytes();
-                result.push(QrSegment::make_bytes(&v));
-            },
-            QrSegmentMode::Numeric => {
-                let s: String = s.iter().collect();
-                result.push(QrSegment::make_numeric(s.as_str()));
-            },
-            QrSegmentMode::Alphanumeric => {
-                let s: String = s.iter().collect();
-                result.push(QrSegment::make_alphanumeric(s.as_str()));
-            },
-            QrSegmentMode::Kanji => {
-                if cfg!(feature = "kanji") {
-                    result.push(make_kanji(s));
+        while not_fit_end - end > 1 {
+            let mid = end + (not_fit_end - end) / 2;
+
+            let mid_fit = fit_capacity(
+                optimizer,
+                text.slice(boundaries[start]..boundaries[mid]),
+                symbol,
+                capacity_bits,
+                &relaxed_options,
+            );
+
+            match mid_fit {
+                Ok(fit) => {
+                    end = mid;
+                    end_fit = fit;
+                },
+                Err(err) => {
+                    if mid == start + 1 {
+                        // not even a single code point fits
+                        return Err(err);
+                    }
+
+                    not_fit_end = mid;
+                },
+            }
+        }
+
+        if relaxed_options != *options {
+            // Under the constraints, a range can need fewer bits than a shorter one, so try the ranges from the longest one which fits without them
+            while end > start {
+                match fit_capacity(
+                    optimizer,
+                    text.slice(boundaries[start]..boundaries[end]),
+                    symbol,
+                    capacity_bits,
+                    options,
+                ) {
+                    Ok(fit) => {
+                        end_fit = fit;
+
+                        break;
+                    },
+                    Err(err) => {
+                        if end == start + 1 {
+                            // not even a single code point fits
+                            return Err(err);
+                        }
+
+                        end -= 1;
+                    },
                 }
-            },
-            _ => unreachable!(),
+            }
         }
 
-        if i >= code_points.len() {
-            return result;
+        chunks.push(SymbolChunk {
+            range:     boundaries[start]..boundaries[end],
+            segments:  end_fit.0,
+            data_bits: end_fit.1,
+        });
+
+        if end == boundaries.len() - 1 {
+            return Ok(chunks);
         }
 
-        cur_mode = char_modes[i];
-        start = i;
+        start = end;
     }
 }
 
-// Calculates and returns the number of bits needed to encode the given
-// segments at the given version. The result is None if a segment has too many
-// characters to fit its length field, or the total bits exceeds usize::MAX.
-fn get_total_bits(segs: &[QrSegment], version: Version) -> Option<usize> {
-    let mut result: usize = 0;
-    for seg in segs {
-        let ccbits = num_char_count_bits(seg.mode(), version);
-        if seg.num_chars() >= 1 << ccbits {
-            return None; // The segment's length doesn't fit the field's bit width
+// Returns the segments of the given text in the given symbol and the number of bits used by them if they fit the capacity.
+fn fit_capacity<'a, T: Text<'a>>(
+    optimizer: &mut Optimizer,
+    text: T,
+    symbol: SymbolVersion,
+    capacity_bits: usize,
+    options: &Options,
+) -> Result<(Vec<Segment>, usize), OptimizeError> {
+    let segs = make_segments_optimally_at_version(
+        optimizer,
+        text,
+        symbol,
+        options,
+        &CodePointConstraints::NONE,
+    )?
+    .ok_or(OptimizeError::InvalidInput)?;
+
+    let data_used_bits = check_fit(&segs, symbol, capacity_bits)?;
+
+    Ok((segs, data_used_bits))
+}
+
+// Returns the number of bits needed to encode the given number of bytes as a single byte-mode segment in the given symbol, or `None` if the symbol cannot hold such a segment.
+fn get_naive_bits(num_bytes: usize, symbol: SymbolVersion) -> Option<usize> {
+    symbol.num_char_count_bits(SegmentMode::Byte).and_then(|ccbits| {
+        if num_bytes < 1 << ccbits {
+            Some(usize::from(symbol.mode_indicator_bits()) + usize::from(ccbits) + num_bytes * 8)
+        } else {
+            None
+        }
+    })
+}
+
+// Returns the bit widths of the segment headers in the given symbol, which decide the optimal segments along with the ECI support.
+fn get_headers(symbol: SymbolVersion) -> (u8, bool, [Option<u8>; NUM_MODES]) {
+    let mut char_count_bits = [None; NUM_MODES];
+
+    for (bits, mode) in char_count_bits.iter_mut().zip(MODE_TYPES) {
+        *bits = symbol.num_char_count_bits(*mode);
+    }
+
+    (symbol.mode_indicator_bits(), symbol.supports_eci(), char_count_bits)
+}
+
+// Returns a new list of segments that is optimal for the given text in the given symbol under the given constraints, or `None` if the symbol cannot encode the text.
+fn make_segments_optimally_at_version<'a, T: Text<'a>>(
+    optimizer: &mut Optimizer,
+    text: T,
+    symbol: SymbolVersion,
+    options: &Options,
+    constraints: &CodePointConstraints,
+) -> Result<Option<Vec<Segment>>, OptimizeError> {
+    if text.len() == 0 {
+        return Ok(Some(Vec::new()));
+    }
+
+    if compute_character_modes(optimizer, text, symbol, options, constraints).is_none() {
+        return Ok(None);
+    }
+
+    // The constraints of the cost model only rule out segments, so the optimal segments without them are also optimal under them if they satisfy them.
+    // Only otherwise is the much slower dynamic programming under the constraints needed
+    if options.strategy == Strategy::Optimal && options.cost_model.is_constrained() {
+        let (num_segments, satisfied) =
+            check_cost_model(&optimizer.states, &options.cost_model, constraints);
+
+        if !satisfied
+            && compute_constrained_character_modes(
+                optimizer,
+                text,
+                symbol,
+                options,
+                num_segments,
+                constraints,
+            )?
+            .is_none()
+        {
+            return Ok(None);
         }
-        result = result.checked_add(4 + usize::from(ccbits) + seg.data().len())?;
     }
-    Some(result)
+
+    Ok(split_into_segments(
+        text,
+        &optimizer.states,
+        &constraints.boundaries,
+        options.implicit_charset,
+        &mut optimizer.bytes,
+    ))
 }
 
-// Returns the bit width of the character count field for a segment in this mode
-// in a QR Code at the given version number. The result is in the range [0, 16].
-fn num_char_count_bits(mode: QrSegmentMode, ver: Version) -> u8 {
-    (match mode {
-        QrSegmentMode::Numeric => [10, 12, 14],
-        QrSegmentMode::Alphanumeric => [9, 11, 13],
-        QrSegmentMode::Byte => [8, 16, 16],
-        QrSegmentMode::Kanji => [8, 10, 12],
-        QrSegmentMode::Eci => [0, 0, 0],
-    })[usize::from((ver.value() + 7) / 17)]
+// Returns `DataTooLong` if the text does not fit the largest symbol even without the constraints of the cost model, which can only add bits,
+// so that the much slower dynamic programming under them is not run for every smaller symbol.
+fn check_relaxed_fit<'a, T: Text<'a>>(
+    optimizer: &mut Optimizer,
+    text: T,
+    symbols: &[SymbolVersion],
+    ecc: QrCodeEcc,
+    options: &Options,
+    constraints: &CodePointConstraints,
+) -> Result<(), OptimizeError> {
+    if options.strategy != Strategy::Optimal || !options.cost_model.is_constrained() {
+        return Ok(());
+    }
+
+    let (symbol, data_capacity_bits) =
+        match symbols.last().and_then(|&symbol| Some((symbol, symbol.num_data_bits(ecc)?))) {
+            Some(largest) => largest,
+            None => return Ok(()),
+        };
+
+    let relaxed_options =
+        options.cost_model(CostModel::new().segment_penalty(options.cost_model.segment_penalty));
+
+    let segs =
+        make_segments_optimally_at_version(optimizer, text, symbol, &relaxed_options, constraints)?;
+
+    // The segments may be too long for their character count fields, but their bits are still a lower bound
+    let required_bits = segs.map_or(0, |segs| {
+        segs.iter()
+            .map(|seg| {
+                usize::from(symbol.mode_indicator_bits())
+                    + usize::from(seg.mode().num_subset_bits())
+                    + usize::from(symbol.num_char_count_bits(seg.mode()).unwrap_or(0))
+                    + seg.data().len()
+            })
+            .sum()
+    });
+
+    if required_bits > data_capacity_bits {
+        Err(OptimizeError::DataTooLong {
+            required_bits,
+            capacity_bits: data_capacity_bits,
+        })
+    } else {
+        Ok(())
+    }
 }
 
-#[cfg(feature = "kanji")]
-/// Returns a segment representing the specified text string encoded in kanji mode.
-fn make_kanji(code_points: &[char]) -> QrSegment {
-    let mut bb = BitBuffer(Vec::new());
+// Returns the number of data segments of the given states, and whether they satisfy the constraints of the cost model, like the ones which `compute_constrained_character_modes` enforces.
+fn check_cost_model(
+    states: &[u8],
+    cost_model: &CostModel,
+    constraints: &CodePointConstraints,
+) -> (usize, bool) {
+    let num_code_points = states.len();
+
+    let mut num_segments = 0;
+    let mut any_short = false;
+    let mut start = 0;
 
-    for &c in code_points {
-        let val = UNICODE_TO_QR_KANJI[c as usize];
+    for end in 1..=num_code_points {
+        let breaks = end < num_code_points && constraints.boundary(end) == BOUNDARY_BREAK;
 
-        if val == -1 {
-            panic!("String contains non-kanji-mode characters");
+        if end < num_code_points && states[end] == states[end - 1] && !breaks {
+            continue;
+        }
+
+        // a segment which ends at a forced boundary, or a pinned one at the end, can be short
+        let exempt =
+            breaks || (end == num_code_points && constraints.pinned_mode(end - 1).is_some());
+
+        let mode = MODE_TYPES[usize::from(states[end - 1]) % NUM_MODES];
+
+        if !exempt && end - start < cost_model.min_run_length(mode) {
+            any_short = true;
         }
 
-        bb.append_bits(val as u32, 13);
+        num_segments += 1;
+        start = end;
     }
 
-    QrSegment::new(QrSegmentMode::Kanji, code_points.len(), bb.0)
+    // a segment may be short if it is the only one
+    let satisfied = cost_model.max_segments.map_or(true, |max| num_segments <= max)
+        && (num_segments <= 1 || !any_short);
+
+    (num_segments, satisfied)
 }
 
-#[cfg(not(feature = "kanji"))]
-/// Returns a segment representing the specified text string encoded in kanji mode.
-fn make_kanji(_code_points: &[char]) -> QrSegment {
-    unreachable!()
+// The constraints of the segments resolved for each code point. Both lists are empty if there is no constraint.
+struct CodePointConstraints {
+    // The index in `MODE_TYPES` of the mode which each code point is pinned to, or `NO_MODE`
+    pinned_modes: Vec<u8>,
+    // Whether a new segment may start, must start or must not start at each code point
+    boundaries:   Vec<u8>,
 }
 
-#[cfg(feature = "kanji")]
-fn is_kanji(c: char) -> bool {
-    let c = c as usize;
-    c < UNICODE_TO_QR_KANJI.len() && UNICODE_TO_QR_KANJI[c] != -1
+impl CodePointConstraints {
+    const NONE: CodePointConstraints = CodePointConstraints {
+        pinned_modes: Vec::new(),
+        boundaries:   Vec::new(),
+    };
+
+    // Resolves the byte offsets of the constraints into the code points of the text. Returns `OptimizeError::InvalidInput` if an offset is not at the boundary of a code point,
+    // a pinned mode cannot encode data, pinned ranges overlap, or a forced boundary is inside a pinned range.
+    fn new<'a, T: Text<'a>>(
+        text: T,
+        constraints: &SegmentConstraints,
+    ) -> Result<CodePointConstraints, OptimizeError> {
+        if constraints.is_empty() {
+            return Ok(CodePointConstraints::NONE);
+        }
+
+        // The byte offset of each code point, and then the length of the text
+        let mut offset = 0;
+        let offsets: Vec<usize> = text
+            .code_points()
+            .map(|c| {
+                let start = offset;
+
+                offset += c.byte_len();
+
+                start
+            })
+            .chain(iter::once(text.len()))
+            .collect();
+
+        let index_of =
+            |offset: usize| offsets.binary_search(&offset).map_err(|_| OptimizeError::InvalidInput);
+
+        let num_code_points = offsets.len() - 1;
+
+        let mut pinned_modes = vec![NO_MODE; num_code_points];
+        let mut boundaries = vec![BOUNDARY_FREE; num_code_points];
+
+        for (range, mode) in constraints.pins.iter() {
+            let (start, end) = (index_of(range.start)?, index_of(range.end)?);
+
+            if start > end {
+                return Err(OptimizeError::InvalidInput);
+            }
+
+            if start == end {
+                continue;
+            }
+
+            let mode =
+                MODE_TYPES.iter().position(|m| m == mode).ok_or(OptimizeError::InvalidInput)? as u8;
+
+            if pinned_modes[start..end].iter().any(|&m| m != NO_MODE) {
+                return Err(OptimizeError::InvalidInput);
+            }
+
+            pinned_modes[start..end].fill(mode);
+            boundaries[start + 1..end].fill(BOUNDARY_JOIN);
+
+            // the data always starts a segment
+            if start > 0 {
+                boundaries[start] = BOUNDARY_BREAK;
+            }
+        }
+
+        // the boundaries after the pinned ranges, which cannot be set before all of the ranges are known
+        for (range, _) in constraints.pins.iter() {
+            let (start, end) = (index_of(range.start)?, index_of(range.end)?);
+
+            if start < end && end < num_code_points && boundaries[end] == BOUNDARY_FREE {
+                boundaries[end] = BOUNDARY_BREAK;
+            }
+        }
+
+        for &offset in constraints.breaks.iter() {
+            let i = index_of(offset)?;
+
+            if 0 < i && i < num_code_points {
+                match boundaries[i] {
+                    BOUNDARY_JOIN => return Err(OptimizeError::InvalidInput),
+                    _ => boundaries[i] = BOUNDARY_BREAK,
+                }
+            }
+        }
+
+        Ok(CodePointConstraints {
+            pinned_modes,
+            boundaries,
+        })
+    }
+
+    // Returns the mode which the code point at the index is pinned to, if any.
+    #[inline]
+    fn pinned_mode(&self, i: usize) -> Option<u8> {
+        self.pinned_modes.get(i).copied().filter(|&m| m != NO_MODE)
+    }
+
+    // Returns whether a new segment may start, must start or must not start at the code point at the index.
+    #[inline]
+    fn boundary(&self, i: usize) -> u8 {
+        self.boundaries.get(i).copied().unwrap_or(BOUNDARY_FREE)
+    }
+}
+
+// The costs of the dynamic programming in a symbol, measured in 1/6 bits.
+struct Costs {
+    // Whether non-ASCII code points can be encoded in byte mode without an ECI designator
+    implicit_non_ascii: bool,
+    // The character set of byte mode without an ECI designator
+    implicit_charset:   Charset,
+    // Whether each ECI state can be used
+    eci_states_allowed: [bool; NUM_ECI_STATES],
+    // Segment header sizes. `None` means the symbol does not support the mode, or the options do not allow it.
+    head_costs:         [Option<usize>; NUM_MODES],
+    // ECI segment size
+    eci_cost:           usize,
+    // The class which a code point needs to be encoded in each mode, and the cost of a code point in the mode.
+    // The byte mode is handled separately because its cost depends on the ECI state.
+    mode_classes:       [u8; NUM_MODES],
+    char_costs:         [usize; NUM_MODES],
+    // The extra cost of every data segment after the first one
+    segment_penalty:    usize,
+}
+
+impl Costs {
+    fn new(symbol: SymbolVersion, options: &Options, num_code_points: usize) -> Self {
+        let mut eci_states_allowed = [false; NUM_ECI_STATES];
+
+        for (allowed, eci) in eci_states_allowed.iter_mut().zip(ECI_STATES) {
+            *allowed = match eci {
+                None => options.eci != EciPolicy::Prefix,
+                Some(charset) => {
+                    symbol.supports_eci()
+                        && options.eci != EciPolicy::Never
+                        && options.charsets.contains(charset)
+                },
+            };
+        }
+
+        let mode_indicator_bits = usize::from(symbol.mode_indicator_bits());
+
+        let mut head_costs = [None::<usize>; NUM_MODES];
+
+        for (head_cost, mode) in head_costs.iter_mut().zip(MODE_TYPES) {
+            if !options.modes.contains(*mode) {
+                continue;
+            }
+
+            *head_cost = symbol.num_char_count_bits(*mode).map(|ccbits| {
+                (mode_indicator_bits + usize::from(mode.num_subset_bits()) + usize::from(ccbits))
+                    * 6
+            });
+        }
+
+        let mut mode_classes = [0u8; NUM_MODES];
+        let mut char_costs = [0usize; NUM_MODES];
+
+        for ((class, char_cost), mode) in
+            mode_classes.iter_mut().zip(char_costs.iter_mut()).zip(MODE_TYPES)
+        {
+            (*class, *char_cost) = match mode {
+                SegmentMode::Alphanumeric => (CLASS_ALPHANUMERIC, 33), // 5.5 bits per alphanumeric char
+                SegmentMode::Numeric => (CLASS_NUMERIC, 20),           // 3.33 bits per digit
+                #[cfg(feature = "kanji")]
+                SegmentMode::Kanji => (CLASS_KANJI, 78), // 13 bits per Shift JIS char
+                #[cfg(feature = "hanzi")]
+                SegmentMode::Hanzi => (CLASS_HANZI, 78), // 13 bits per GB 2312 char
+                _ => (0, 0),
+            };
+        }
+
+        // A penalty this large already outweighs any number of bits, and the sums of the costs cannot overflow
+        let segment_penalty = match options.strategy {
+            Strategy::Optimal => {
+                options.cost_model.segment_penalty.min(usize::MAX / 12 / (num_code_points + 1)) * 6
+            },
+            Strategy::AnnexJ => 0,
+        };
+
+        Costs {
+            implicit_non_ascii: options.eci == EciPolicy::Never,
+            implicit_charset: options.implicit_charset,
+            eci_states_allowed,
+            head_costs,
+            eci_cost: (mode_indicator_bits + ECI_ASSIGNMENT_BITS) * 6,
+            mode_classes,
+            char_costs,
+            segment_penalty,
+        }
+    }
+
+    // Returns the cost of the first segment header in the state, including its ECI designator, or `None` if the state cannot be used.
+    #[inline]
+    fn start_cost(&self, state: usize) -> Option<usize> {
+        let (e, m) = (state / NUM_MODES, state % NUM_MODES);
+
+        if !self.eci_states_allowed[e] {
+            return None;
+        }
+
+        self.head_costs[m]
+            .map(|head_cost| head_cost + if ECI_STATES[e].is_some() { self.eci_cost } else { 0 })
+    }
+
+    // Returns the cost of a new segment header in the `to` state after a segment which ends in the `from_eci` ECI state, including the ECI designator and the penalty, or `None` if the transition is impossible.
+    #[inline]
+    fn switch_cost(&self, from_eci: usize, to: usize) -> Option<usize> {
+        let (to_eci, to_mode) = (to / NUM_MODES, to % NUM_MODES);
+
+        if !self.eci_states_allowed[to_eci] {
+            return None;
+        }
+
+        let head_cost = self.head_costs[to_mode]? + self.segment_penalty;
+
+        if from_eci == to_eci {
+            Some(head_cost)
+        } else if ECI_STATES[to_eci].is_some() {
+            Some(self.eci_cost + head_cost)
+        } else {
+            // an ECI designator cannot be revoked
+            None
+        }
+    }
+
+    // Returns the cost of the code point in each state, or `None` if the state cannot encode it.
+    #[inline]
+    fn code_point_costs<C: CodePoint>(&self, c: C) -> [Option<usize>; NUM_STATES] {
+        let class = c.classify();
+
+        let mut costs = [None::<usize>; NUM_STATES];
+        let mut buffer = [0u8; 4];
+
+        for (e, eci) in ECI_STATES.iter().enumerate() {
+            for m in 0..NUM_MODES {
+                costs[e * NUM_MODES + m] = if MODE_TYPES[m] == SegmentMode::Byte {
+                    if self.eci_states_allowed[e]
+                        && (eci.is_some() || self.implicit_non_ascii || c.is_ascii())
+                    {
+                        c.encode(Some(eci.unwrap_or(self.implicit_charset)), &mut buffer)
+                            .map(|len| len * 8 * 6)
+                    } else {
+                        None
+                    }
+                } else if class & self.mode_classes[m] != 0 {
+                    Some(self.char_costs[m])
+                } else {
+                    None
+                };
+            }
+        }
+
+        costs
+    }
+}
+
+// Removes the costs of the states which are not in the mode of the given index in `MODE_TYPES`.
+#[inline]
+fn retain_mode(char_costs: &mut [Option<usize>; NUM_STATES], mode: u8) {
+    for (s, char_cost) in char_costs.iter_mut().enumerate() {
+        if s % NUM_MODES != usize::from(mode) {
+            *char_cost = None;
+        }
+    }
+}
+
+// Computes the optimal state per code point based on the given text and symbol under the given constraints, but not under the ones of the cost model, into `optimizer.states`, or returns `None` if the symbol cannot encode the text.
+fn compute_character_modes<'a, T: Text<'a>>(
+    optimizer: &mut Optimizer,
+    text: T,
+    symbol: SymbolVersion,
+    options: &Options,
+    constraints: &CodePointConstraints,
+) -> Option<()> {
+    let num_code_points = text.code_points().count();
+    let costs = Costs::new(symbol, options, num_code_points);
+
+    // Whether the mode of each code point is fixed in `optimizer.fixed_modes`, so that only the ECI states are chosen
+    let fixed = match options.strategy {
+        Strategy::Optimal => false,
+        Strategy::AnnexJ => {
+            compute_annex_j_modes(&mut optimizer.fixed_modes, text, symbol, options);
+
+            // the pinned modes override the mode selection
+            for (fixed_mode, &pinned_mode) in
+                optimizer.fixed_modes.iter_mut().zip(constraints.pinned_modes.iter())
+            {
+                if pinned_mode != NO_MODE {
+                    *fixed_mode = pinned_mode;
+                }
+            }
+
+            true
+        },
+    };
+
+    let Optimizer {
+        traceback,
+        states,
+        fixed_modes,
+        ..
+    } = optimizer;
+
+    // traceback[i][j] represents the state to encode the code point at index i
+    // such that the final segment ends in state j and the total number of bits is minimized over all possible choices
+    traceback.clear();
+    traceback.resize(num_code_points, [NO_STATE; NUM_STATES]);
+
+    // At the beginning of each iteration of the loop below,
+    // prevCosts[j] is the exact minimum number of 1/6 bits needed to encode the entire string prefix of length i, and end in state j
+    let mut prev_costs = [None::<usize>; NUM_STATES];
+
+    for (s, prev_cost) in prev_costs.iter_mut().enumerate() {
+        *prev_cost = costs.start_cost(s);
+    }
+
+    // Calculate costs using dynamic programming
+    for (i, (c, row)) in text.code_points().zip(traceback.iter_mut()).enumerate() {
+        let mut char_costs = costs.code_point_costs(c);
+
+        let fixed_mode = if fixed { Some(fixed_modes[i]) } else { constraints.pinned_mode(i) };
+
+        if let Some(fixed_mode) = fixed_mode {
+            retain_mode(&mut char_costs, fixed_mode);
+        }
+
+        let mut cur_costs = [None::<usize>; NUM_STATES];
+
+        // Extend a segment if possible
+        for (s, prev_cost) in prev_costs.iter().copied().enumerate() {
+            if let (Some(prev_cost), Some(char_cost)) = (prev_cost, char_costs[s]) {
+                cur_costs[s] = Some(prev_cost + char_cost);
+                row[s] = s as u8;
+            }
+        }
+
+        // The constraints may require the segment to go on after this code point, or to end here
+        let next_boundary = constraints.boundary(i + 1);
+
+        if next_boundary == BOUNDARY_JOIN {
+            prev_costs = cur_costs;
+
+            continue;
+        }
+
+        // Start new segment at the end to switch modes, or to emit an ECI designator.
+        // Only the cheapest state to end a segment in each ECI state matters, with the fractional bits rounded up
+        let mut segment_ends = [None::<(usize, usize)>; NUM_ECI_STATES];
+
+        for (from, from_cost) in cur_costs.iter().copied().enumerate() {
+            if let Some(from_cost) = from_cost {
+                let end_cost = (from_cost + 5) / 6 * 6;
+                let segment_end = &mut segment_ends[from / NUM_MODES];
+
+                if segment_end.map_or(true, |(cost, _)| end_cost < cost) {
+                    *segment_end = Some((end_cost, from));
+                }
+            }
+        }
+
+        if next_boundary == BOUNDARY_BREAK {
+            cur_costs = [None; NUM_STATES];
+        }
+
+        for to in 0..NUM_STATES {
+            for (from_eci, segment_end) in segment_ends.iter().copied().enumerate() {
+                let (end_cost, from) = match segment_end {
+                    Some(segment_end) => segment_end,
+                    None => continue,
+                };
+
+                let new_cost = match costs.switch_cost(from_eci, to) {
+                    Some(switch_cost) => end_cost + switch_cost,
+                    None => continue,
+                };
+
+                if cur_costs[to].map_or(true, |cur_cost| new_cost < cur_cost) {
+                    cur_costs[to] = Some(new_cost);
+                    row[to] = from as u8;
+                }
+            }
+        }
+
+        prev_costs = cur_costs;
+    }
+
+    // Find optimal ending state
+    let mut cur_state = None::<usize>;
+
+    let mut min_cost = 0;
+
+    for (i, cost) in prev_costs.iter().copied().enumerate() {
+        if let Some(cost) = cost {
+            if cur_state.is_none() || cost < min_cost {
+                min_cost = cost;
+                cur_state = Some(i);
+            }
+        }
+    }
+
+    let mut cur_state = cur_state?;
+
+    states.clear();
+    states.resize(num_code_points, NO_STATE);
+
+    // Get optimal state for each code point by tracing backwards
+    for i in (0..num_code_points).rev() {
+        let state = traceback[i][cur_state];
+
+        if state == NO_STATE {
+            return None;
+        }
+
+        states[i] = state;
+        cur_state = usize::from(state);
+    }
+
+    Some(())
+}
+
+// The thresholds of the mode selection of ISO/IEC 18004 Annex J in characters,
+// for the classes of the character count fields of QR code versions 1–9, 10–26 and 27–40.
+// In the initial mode selection, a run shorter than these which is followed by the data of the other mode starts in the other mode
+const ANNEX_J_ALPHANUMERIC_BEFORE_BYTE: [usize; 3] = [6, 7, 8];
+const ANNEX_J_NUMERIC_BEFORE_BYTE: [usize; 3] = [4, 4, 5];
+const ANNEX_J_NUMERIC_BEFORE_ALPHANUMERIC: [usize; 3] = [7, 8, 9];
+// In the byte mode and in the alphanumeric mode, a run at least as long as these switches to its mode
+#[cfg(feature = "kanji")]
+const ANNEX_J_KANJI_IN_BYTE: [usize; 3] = [9, 12, 13];
+const ANNEX_J_NUMERIC_IN_BYTE: [usize; 3] = [6, 8, 9];
+const ANNEX_J_ALPHANUMERIC_IN_BYTE: [usize; 3] = [11, 15, 16];
+const ANNEX_J_NUMERIC_IN_ALPHANUMERIC: [usize; 3] = [13, 15, 17];
+
+// Returns the index of the mode in `MODE_TYPES`.
+#[inline]
+fn mode_index(mode: SegmentMode) -> u8 {
+    MODE_TYPES.iter().position(|&m| m == mode).unwrap() as u8
+}
+
+// Chooses the mode of each code point by the greedy mode selection of ISO/IEC 18004 Annex J, and writes the indices of the modes in `MODE_TYPES` into `fixed_modes`.
+// The modes which the options do not allow are treated as if their characters were only in the byte mode.
+fn compute_annex_j_modes<'a, T: Text<'a>>(
+    fixed_modes: &mut Vec<u8>,
+    text: T,
+    symbol: SymbolVersion,
+    options: &Options,
+) {
+    let mut allowed_classes = 0;
+
+    if options.modes.contains(SegmentMode::Numeric) {
+        allowed_classes |= CLASS_NUMERIC;
+    }
+
+    if options.modes.contains(SegmentMode::Alphanumeric) {
+        allowed_classes |= CLASS_ALPHANUMERIC;
+    }
+
+    #[cfg(feature = "kanji")]
+    if options.modes.contains(SegmentMode::Kanji) {
+        allowed_classes |= CLASS_KANJI;
+    }
+
+    // Micro QR code and rMQR code symbols use the thresholds of the QR code versions with the same character count field of the numeric mode
+    let t = match symbol.num_char_count_bits(SegmentMode::Numeric).unwrap_or(10) {
+        0..=10 => 0,
+        11..=12 => 1,
+        _ => 2,
+    };
+
+    // The classes of the code points, each of which is replaced by its mode once chosen. The choice only looks ahead
+    let classes = fixed_modes;
+
+    classes.clear();
+    classes.extend(text.code_points().map(|c| c.classify() & allowed_classes));
+
+    // Returns the length of the run of the class from the index, counting up to the limit, and the class of the code point after the run, if any
+    let run = |classes: &[u8], i: usize, class: u8, limit: usize| {
+        let len = classes[i..].iter().take(limit).take_while(|&&c| c & class != 0).count();
+
+        (len, if len < limit { classes.get(i + len).copied() } else { None })
+    };
+
+    let is_byte_only = |class: u8| class == 0;
+    let is_alphanumeric_only =
+        |class: u8| class & CLASS_ALPHANUMERIC != 0 && class & CLASS_NUMERIC == 0;
+
+    // The mode of a code point which cannot stay in the current mode
+    let mode_of = |class: u8| {
+        if class & CLASS_NUMERIC != 0 {
+            SegmentMode::Numeric
+        } else if class & CLASS_ALPHANUMERIC != 0 {
+            SegmentMode::Alphanumeric
+        } else {
+            #[cfg(feature = "kanji")]
+            if class & CLASS_KANJI != 0 {
+                return SegmentMode::Kanji;
+            }
+
+            SegmentMode::Byte
+        }
+    };
+
+    // The initial mode selection, which is also used to leave the kanji mode
+    let initial_mode = |classes: &[u8], i: usize| {
+        let class = classes[i];
+
+        if class & CLASS_NUMERIC != 0 {
+            let limit = ANNEX_J_NUMERIC_BEFORE_BYTE[t].max(ANNEX_J_NUMERIC_BEFORE_ALPHANUMERIC[t]);
+            let (len, next) = run(classes, i, CLASS_NUMERIC, limit);
+
+            match next {
+                Some(next) if is_byte_only(next) && len < ANNEX_J_NUMERIC_BEFORE_BYTE[t] => {
+                    SegmentMode::Byte
+                },
+                Some(next)
+                    if is_alphanumeric_only(next)
+                        && len < ANNEX_J_NUMERIC_BEFORE_ALPHANUMERIC[t] =>
+                {
+                    SegmentMode::Alphanumeric
+                },
+                _ => SegmentMode::Numeric,
+            }
+        } else if class & CLASS_ALPHANUMERIC != 0 {
+            let (len, next) =
+                run(classes, i, CLASS_ALPHANUMERIC, ANNEX_J_ALPHANUMERIC_BEFORE_BYTE[t]);
+
+            match next {
+                Some(next) if is_byte_only(next) && len < ANNEX_J_ALPHANUMERIC_BEFORE_BYTE[t] => {
+                    SegmentMode::Byte
+                },
+                _ => SegmentMode::Alphanumeric,
+            }
+        } else {
+            mode_of(class)
+        }
+    };
+
+    let mut mode = None;
+
+    for i in 0..classes.len() {
+        let class = classes[i];
+
+        let next_mode = match mode {
+            None => initial_mode(classes, i),
+            Some(SegmentMode::Byte) => {
+                let switch_to =
+                    |class: u8, threshold: usize| run(classes, i, class, threshold).0 >= threshold;
+
+                #[cfg(feature = "kanji")]
+                if switch_to(CLASS_KANJI, ANNEX_J_KANJI_IN_BYTE[t]) {
+                    mode = Some(SegmentMode::Kanji);
+                    classes[i] = mode_index(SegmentMode::Kanji);
+
+                    continue;
+                }
+
+                if switch_to(CLASS_NUMERIC, ANNEX_J_NUMERIC_IN_BYTE[t]) {
+                    SegmentMode::Numeric
+                } else if switch_to(CLASS_ALPHANUMERIC, ANNEX_J_ALPHANUMERIC_IN_BYTE[t]) {
+                    SegmentMode::Alphanumeric
+                } else {
+                    SegmentMode::Byte
+                }
+            },
+            Some(SegmentMode::Alphanumeric) if class & CLASS_ALPHANUMERIC != 0 => {
+                if run(classes, i, CLASS_NUMERIC, ANNEX_J_NUMERIC_IN_ALPHANUMERIC[t]).0
+                    >= ANNEX_J_NUMERIC_IN_ALPHANUMERIC[t]
+                {
+                    SegmentMode::Numeric
+                } else {
+                    SegmentMode::Alphanumeric
+                }
+            },
+            Some(SegmentMode::Numeric) if class & CLASS_NUMERIC != 0 => SegmentMode::Numeric,
+            Some(SegmentMode::Alphanumeric | SegmentMode::Numeric) => mode_of(class),
+            #[cfg(feature = "kanji")]
+            Some(SegmentMode::Kanji) if class & CLASS_KANJI != 0 => SegmentMode::Kanji,
+            Some(_) => initial_mode(classes, i),
+        };
+
+        mode = Some(next_mode);
+        classes[i] = mode_index(next_mode);
+    }
+}
+
+// Computes the optimal state per code point like `compute_character_modes`, but also under the constraints of the cost model, given the number of data segments without them.
+// Each slot of this dynamic programming is a state along with the number of data segments so far, which is a layer, and the length of the current segment,
+// which is capped at the minimum run length of the mode. A segment can only be ended in the slot of the capped length.
+// Returns `OutOfMemory` if the traceback table cannot be allocated.
+fn compute_constrained_character_modes<'a, T: Text<'a>>(
+    optimizer: &mut Optimizer,
+    text: T,
+    symbol: SymbolVersion,
+    options: &Options,
+    num_relaxed_segments: usize,
+    constraints: &CodePointConstraints,
+) -> Result<Option<()>, OptimizeError> {
+    let num_code_points = text.code_points().count();
+    let costs = &Costs::new(symbol, options, num_code_points);
+    let cost_model = &options.cost_model;
+
+    let Optimizer {
+        constrained_traceback: traceback,
+        states,
+        ..
+    } = optimizer;
+
+    // Without a maximum number of segments, the layers only tell the first segment from the others,
+    // since a segment may be shorter than the minimum run length of its mode if it is the only one.
+    // The constraints seldom need more segments than the optimal segments without them
+    let (num_layers, saturating) = match cost_model.max_segments {
+        Some(max_segments) => (max_segments.min(num_code_points).min(num_relaxed_segments), false),
+        None => (2, true),
+    };
+
+    if num_layers == 0 {
+        return Ok(None);
+    }
+
+    // The slots of each state in a layer start at offsets[s], and a segment in the state can be ended in the last one if can_end[s] is true
+    let mut offsets = [0usize; NUM_STATES + 1];
+    let mut can_end = [true; NUM_STATES];
+
+    for s in 0..NUM_STATES {
+        let min_run_length = cost_model.min_run_length(MODE_TYPES[s % NUM_MODES]);
+
+        let num_slots = if min_run_length <= num_code_points {
+            min_run_length
+        } else {
+            // no segment in this state is long enough, so the lengths do not matter
+            can_end[s] = false;
+
+            1
+        };
+
+        offsets[s + 1] = offsets[s] + num_slots;
+    }
+
+    let layer_size = offsets[NUM_STATES];
+    let num_slots = num_layers * layer_size;
+
+    let mut slot_states = Vec::with_capacity(layer_size);
+
+    for s in 0..NUM_STATES {
+        slot_states.extend(iter::repeat(s as u8).take(offsets[s + 1] - offsets[s]));
+    }
+
+    // traceback[i * num_slots + j] represents the slot of the code point at index i - 1
+    // such that the code point at index i is encoded in slot j and the total number of bits is minimized over all possible choices
+    let traceback_len = num_code_points.checked_mul(num_slots).ok_or(OptimizeError::OutOfMemory)?;
+
+    traceback.clear();
+    traceback.try_reserve(traceback_len).map_err(|_| OptimizeError::OutOfMemory)?;
+    traceback.resize(traceback_len, NO_SLOT);
+
+    let mut prev_costs = vec![None::<usize>; num_slots];
+    let mut cur_costs = vec![None::<usize>; num_slots];
+
+    // The cheapest slot to end a segment in each layer and each ECI state
+    let mut segment_ends = vec![[None::<(usize, usize)>; NUM_ECI_STATES]; num_layers];
+
+    // Calculate costs using dynamic programming
+    for (i, (c, row)) in text.code_points().zip(traceback.chunks_exact_mut(num_slots)).enumerate() {
+        let mut char_costs = costs.code_point_costs(c);
+
+        if let Some(pinned_mode) = constraints.pinned_mode(i) {
+            retain_mode(&mut char_costs, pinned_mode);
+        }
+
+        let boundary = constraints.boundary(i);
+
+        cur_costs.fill(None);
+
+        if i == 0 {
+            // Start the first segment
+            for s in 0..NUM_STATES {
+                if let (Some(start_cost), Some(char_cost)) = (costs.start_cost(s), char_costs[s]) {
+                    cur_costs[offsets[s]] = Some(start_cost + char_cost);
+                }
+            }
+        } else {
+            // Extend a segment if possible, unless a new one must start at this code point
+            for layer_start in
+                (0..num_slots).step_by(layer_size).filter(|_| boundary != BOUNDARY_BREAK)
+            {
+                for s in 0..NUM_STATES {
+                    let char_cost = match char_costs[s] {
+                        Some(char_cost) => char_cost,
+                        None => continue,
+                    };
+
+                    let (first, last) =
+                        (layer_start + offsets[s], layer_start + offsets[s + 1] - 1);
+
+                    for (from, prev_cost) in (first..).zip(prev_costs[first..=last].iter().copied())
+                    {
+                        if let Some(prev_cost) = prev_cost {
+                            let to = (from + 1).min(last);
+                            let new_cost = prev_cost + char_cost;
+
+                            if cur_costs[to].map_or(true, |cur_cost| new_cost < cur_cost) {
+                                cur_costs[to] = Some(new_cost);
+                                row[to] = from as u32;
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Start a new segment to switch modes, or to emit an ECI designator.
+            // Only the cheapest slot to end a segment in each layer and ECI state matters, with the fractional bits rounded up.
+            // A segment which ends at a forced boundary can be ended in any slot
+            for (layer, layer_ends) in segment_ends.iter_mut().enumerate() {
+                *layer_ends = [None; NUM_ECI_STATES];
+
+                if boundary == BOUNDARY_JOIN {
+                    continue;
+                }
+
+                for s in 0..NUM_STATES {
+                    let last = layer * layer_size + offsets[s + 1] - 1;
+
+                    let first = if boundary == BOUNDARY_BREAK {
+                        layer * layer_size + offsets[s]
+                    } else if can_end[s] {
+                        last
+                    } else {
+                        continue;
+                    };
+
+                    for (from, from_cost) in (first..).zip(prev_costs[first..=last].iter().copied())
+                    {
+                        if let Some(from_cost) = from_cost {
+                            let end_cost = (from_cost + 5) / 6 * 6;
+                            let segment_end = &mut layer_ends[s / NUM_MODES];
+
+                            if segment_end.map_or(true, |(cost, _)| end_cost < cost) {
+                                *segment_end = Some((end_cost, from));
+                            }
+                        }
+                    }
+                }
+            }
+
+            for (layer, layer_ends) in segment_ends.iter().enumerate() {
+                let to_layer = if layer + 1 < num_layers {
+                    layer + 1
+                } else if saturating {
+                    layer
+                } else {
+                    continue;
+                };
+
+                for to in 0..NUM_STATES {
+                    let char_cost = match char_costs[to] {
+                        Some(char_cost) => char_cost,
+                        None => continue,
+                    };
+
+                    let to_slot = to_layer * layer_size + offsets[to];
+
+                    for (from_eci, segment_end) in layer_ends.iter().copied().enumerate() {
+                        let (end_cost, from) = match segment_end {
+                            Some(segment_end) => segment_end,
+                            None => continue,
+                        };
+
+                        let new_cost = match costs.switch_cost(from_eci, to) {
+                            Some(switch_cost) => end_cost + switch_cost + char_cost,
+                            None => continue,
+                        };
+
+                        if cur_costs[to_slot].map_or(true, |cur_cost| new_cost < cur_cost) {
+                            cur_costs[to_slot] = Some(new_cost);
+                            row[to_slot] = from as u32;
+                        }
+                    }
+                }
+            }
+        }
+
+        mem::swap(&mut prev_costs, &mut cur_costs);
+    }
+
+    // Find optimal ending slot, where the segment is long enough unless it is the only one or it is pinned
+    let mut cur_slot = None::<usize>;
+
+    let mut min_cost = 0;
+
+    let pinned_end = constraints.pinned_mode(num_code_points - 1).is_some();
+
+    for (j, cost) in prev_costs.iter().copied().enumerate() {
+        let s = usize::from(slot_states[j % layer_size]);
+
+        if j >= layer_size && !pinned_end && !(can_end[s] && j % layer_size == offsets[s + 1] - 1) {
+            continue;
+        }
+
+        if let Some(cost) = cost {
+            if cur_slot.is_none() || cost < min_cost {
+                min_cost = cost;
+                cur_slot = Some(j);
+            }
+        }
+    }
+
+    let mut cur_slot = match cur_slot {
+        Some(cur_slot) => cur_slot,
+        None => return Ok(None),
+    };
+
+    states.clear();
+    states.resize(num_code_points, NO_STATE);
+
+    // Get optimal state for each code point by tracing backwards
+    for i in (0..num_code_points).rev() {
+        states[i] = slot_states[cur_slot % layer_size];
+
+        if i > 0 {
+            let slot = traceback[i * num_slots + cur_slot];
+
+            if slot == NO_SLOT {
+                return Ok(None);
+            }
+
+            cur_slot = slot as usize;
+        }
+    }
+
+    Ok(Some(()))
+}
+
+// Returns a new list of segments based on the given text and states, such that consecutive code points in the same state are put into the same segment unless a boundary is forced between them. Returns `None` if a code point cannot be encoded in its mode.
+fn split_into_segments<'a, T: Text<'a>>(
+    text: T,
+    states: &[u8],
+    boundaries: &[u8],
+    implicit_charset: Charset,
+    bytes: &mut Vec<u8>,
+) -> Option<Vec<Segment>> {
+    let eci_of = |state: u8| ECI_STATES[usize::from(state) / NUM_MODES];
+
+    let mut result = Vec::new();
+
+    // Accumulate run of states
+    let mut cur_state = *states.first()?;
+
+    if let Some(charset) = eci_of(cur_state) {
+        result.push(Segment::make_eci(charset.eci_assignment()));
+    }
+
+    let (mut start, mut end) = (0, 0);
+
+    for (i, (c, &state)) in text.code_points().zip(states).enumerate() {
+        if state != cur_state || boundaries.get(i) == Some(&BOUNDARY_BREAK) {
+            result.push(make_segment(text.slice(start..end), cur_state, implicit_charset, bytes)?);
+
+            if eci_of(state) != eci_of(cur_state) {
+                if let Some(charset) = eci_of(state) {
+                    result.push(Segment::make_eci(charset.eci_assignment()));
+                }
+            }
+
+            cur_state = state;
+            start = end;
+        }
+
+        end += c.byte_len();
+    }
+
+    result.push(make_segment(text.slice(start..end), cur_state, implicit_charset, bytes)?);
+
+    Some(result)
+}
+
+// Returns a segment representing the given text in the mode of the given state, where byte mode without an ECI designator is in the implicit character set. The bytes re-encoded in another character set are written into the buffer.
+fn make_segment<'a, T: Text<'a>>(
+    text: T,
+    state: u8,
+    implicit_charset: Charset,
+    bytes: &mut Vec<u8>,
+) -> Option<Segment> {
+    let state = usize::from(state);
+
+    match MODE_TYPES[state % NUM_MODES] {
+        SegmentMode::Byte => match ECI_STATES[state / NUM_MODES].unwrap_or(implicit_charset) {
+            // the text is already in UTF-8, or is binary data which is never re-encoded
+            Charset::Utf8 => Some(Segment::make_bytes(text.as_bytes())),
+            charset => {
+                let charset = Some(charset);
+                let mut buffer = [0u8; 4];
+
+                bytes.clear();
+
+                for c in text.code_points() {
+                    let len = c.encode(charset, &mut buffer)?;
+
+                    bytes.extend_from_slice(&buffer[..len]);
+                }
+
+                Some(Segment::make_bytes(bytes))
+            },
+        },
+        // numeric and alphanumeric characters are ASCII
+        SegmentMode::Numeric => {
+            Some(Segment::make_numeric(unsafe { from_utf8_unchecked(text.as_bytes()) }))
+        },
+        SegmentMode::Alphanumeric => {
+            Some(Segment::make_alphanumeric(unsafe { from_utf8_unchecked(text.as_bytes()) }))
+        },
+        SegmentMode::Kanji => make_kanji(text),
+        SegmentMode::Hanzi => make_hanzi(text),
+        _ => None,
+    }
+}
+
+// Calculates and returns the number of bits needed to encode the given
+// segments in the given symbol. The result is None if a segment is in a mode which
+// the symbol does not support, has too many characters to fit its length field,
+// or the total bits exceeds usize::MAX.
+pub(crate) fn get_total_bits(segs: &[Segment], symbol: SymbolVersion) -> Option<usize> {
+    let mut result: usize = 0;
+    for seg in segs {
+        let ccbits = symbol.num_char_count_bits(seg.mode())?;
+        if seg.num_chars() >= 1 << ccbits {
+            return None; // The segment's length doesn't fit the field's bit width
+        }
+        result = result.checked_add(
+            usize::from(symbol.mode_indicator_bits())
+                + usize::from(seg.mode().num_subset_bits())
+                + usize::from(ccbits)
+                + seg.data().len(),
+        )?;
+    }
+    Some(result)
+}
+
+#[cfg(feature = "kanji")]
+/// Returns a segment representing the specified text string encoded in kanji mode, or `None` if it contains non-kanji-mode characters.
+fn make_kanji<'a, T: Text<'a>>(text: T) -> Option<Segment> {
+    Segment::make_double_byte(SegmentMode::Kanji, text.code_points().map(CodePoint::to_kanji))
+}
+
+#[cfg(not(feature = "kanji"))]
+/// Returns a segment representing the specified text string encoded in kanji mode, which is never supported.
+fn make_kanji<'a, T: Text<'a>>(_text: T) -> Option<Segment> {
+    None
+}
+
+#[cfg(feature = "hanzi")]
+/// Returns a segment representing the specified text string encoded in Hanzi mode, or `None` if it contains non-hanzi-mode characters.
+fn make_hanzi<'a, T: Text<'a>>(text: T) -> Option<Segment> {
+    Segment::make_double_byte(SegmentMode::Hanzi, text.code_points().map(CodePoint::to_hanzi))
+}
+
+#[cfg(not(feature = "hanzi"))]
+/// Returns a segment representing the specified text string encoded in Hanzi mode, which is never supported.
+fn make_hanzi<'a, T: Text<'a>>(_text: T) -> Option<Segment> {
+    None
 }
 
 // Returns the number of 8-bit data (i.e. not error correction) codewords contained in any
 // QR Code of the given version number and error correction level, with remainder bits discarded.
 // This stateless pure function could be implemented as a (40*4)-cell lookup table.
-fn get_num_data_codewords(ver: Version, ecl: QrCodeEcc) -> usize {
+pub(crate) fn get_num_data_codewords(ver: Version, ecl: QrCodeEcc) -> usize {
     get_num_raw_data_modules(ver) / 8
         - table_get(&ECC_CODEWORDS_PER_BLOCK, ver, ecl)
             * table_get(&NUM_ERROR_CORRECTION_BLOCKS, ver, ecl)
@@ -0,0 +1,48 @@
+#[cfg(feature = "std")]
+pub use qrcode_generator::qrcodegen::{QrCodeEcc, Version};
+
+/// The error correction level in a QR code symbol. With the `std` feature, it is the `QrCodeEcc` type of `qrcodegen`.
+#[cfg(not(feature = "std"))]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum QrCodeEcc {
+    /// The symbol can tolerate about 7% erroneous codewords.
+    Low,
+    /// The symbol can tolerate about 15% erroneous codewords.
+    Medium,
+    /// The symbol can tolerate about 25% erroneous codewords.
+    Quartile,
+    /// The symbol can tolerate about 30% erroneous codewords.
+    High,
+}
+
+/// A version number of QR code symbols between 1 and 40 (inclusive). With the `std` feature, it is the `Version` type of `qrcodegen`.
+#[cfg(not(feature = "std"))]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Version(u8);
+
+#[cfg(not(feature = "std"))]
+impl Version {
+    /// The maximum version number.
+    pub const MAX: Version = Version(40);
+    /// The minimum version number.
+    pub const MIN: Version = Version(1);
+
+    /// Creates a version object from the given number.
+    ///
+    /// Panics if the number is outside the range [1, 40].
+    #[inline]
+    pub fn new(ver: u8) -> Self {
+        assert!(
+            (Version::MIN.value()..=Version::MAX.value()).contains(&ver),
+            "Version number out of range"
+        );
+
+        Self(ver)
+    }
+
+    /// Returns the value, which is in the range [1, 40].
+    #[inline]
+    pub fn value(self) -> u8 {
+        self.0
+    }
+}
@@ -0,0 +1,371 @@
+use alloc::vec::Vec;
+use core::ops::BitOr;
+
+#[cfg(feature = "std")]
+use qrcode_generator::{qrcodegen::QrSegmentMode, QrSegment};
+
+#[cfg(feature = "hanzi")]
+use crate::double_byte_table::HANZI_TABLE;
+#[cfg(feature = "kanji")]
+use crate::double_byte_table::KANJI_TABLE;
+use crate::{qr_segment_advanced::ALPHANUMERIC_CHARSET, Version};
+
+/// Describes how a segment's data bits are interpreted. Unlike `QrSegmentMode`, it also covers the modes which `qrcodegen` does not support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SegmentMode {
+    Numeric,
+    Alphanumeric,
+    Byte,
+    Kanji,
+    /// The Hanzi mode of GB/T 18284 with the GB 2312 subset. Text can only be encoded in or decoded from it with the `hanzi` feature.
+    Hanzi,
+    Eci,
+    /// The Structured Append header, which puts a symbol in a sequence of up to 16 symbols.
+    StructuredAppend,
+}
+
+impl SegmentMode {
+    /// Returns the 4-bit mode indicator of this mode in a QR code.
+    #[inline]
+    pub const fn mode_bits(self) -> u32 {
+        match self {
+            SegmentMode::Numeric => 0x1,
+            SegmentMode::Alphanumeric => 0x2,
+            SegmentMode::Byte => 0x4,
+            SegmentMode::Kanji => 0x8,
+            SegmentMode::Hanzi => 0xD,
+            SegmentMode::Eci => 0x7,
+            SegmentMode::StructuredAppend => 0x3,
+        }
+    }
+
+    /// Returns the bit width of the character count field for a segment in this mode in a QR code at the given version number. The result is in the range [0, 16].
+    #[inline]
+    pub fn num_char_count_bits(self, ver: Version) -> u8 {
+        (match self {
+            SegmentMode::Numeric => [10, 12, 14],
+            SegmentMode::Alphanumeric => [9, 11, 13],
+            SegmentMode::Byte => [8, 16, 16],
+            SegmentMode::Kanji => [8, 10, 12],
+            SegmentMode::Hanzi => [8, 10, 12],
+            SegmentMode::Eci | SegmentMode::StructuredAppend => [0, 0, 0],
+        })[usize::from((ver.value() + 7) / 17)]
+    }
+
+    /// Returns the bit width of the fields between the mode indicator and the character count field. Only the Hanzi mode has one, the 4-bit subset indicator.
+    #[inline]
+    pub(crate) const fn num_subset_bits(self) -> u8 {
+        match self {
+            SegmentMode::Hanzi => 4,
+            _ => 0,
+        }
+    }
+
+    /// Returns the corresponding mode of `qrcodegen`, if any.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub const fn to_qr_segment_mode(self) -> Option<QrSegmentMode> {
+        match self {
+            SegmentMode::Numeric => Some(QrSegmentMode::Numeric),
+            SegmentMode::Alphanumeric => Some(QrSegmentMode::Alphanumeric),
+            SegmentMode::Byte => Some(QrSegmentMode::Byte),
+            SegmentMode::Kanji => Some(QrSegmentMode::Kanji),
+            SegmentMode::Hanzi => None,
+            SegmentMode::Eci => Some(QrSegmentMode::Eci),
+            SegmentMode::StructuredAppend => None,
+        }
+    }
+
+    /// Returns the flag of this mode in `SegmentModes`. The modes which don't encode data have none.
+    #[inline]
+    const fn flag(self) -> u8 {
+        match self {
+            SegmentMode::Numeric => 0b00001,
+            SegmentMode::Alphanumeric => 0b00010,
+            SegmentMode::Byte => 0b00100,
+            SegmentMode::Kanji => 0b01000,
+            SegmentMode::Hanzi => 0b10000,
+            SegmentMode::Eci | SegmentMode::StructuredAppend => 0,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<QrSegmentMode> for SegmentMode {
+    #[inline]
+    fn from(mode: QrSegmentMode) -> Self {
+        match mode {
+            QrSegmentMode::Numeric => SegmentMode::Numeric,
+            QrSegmentMode::Alphanumeric => SegmentMode::Alphanumeric,
+            QrSegmentMode::Byte => SegmentMode::Byte,
+            QrSegmentMode::Kanji => SegmentMode::Kanji,
+            QrSegmentMode::Eci => SegmentMode::Eci,
+        }
+    }
+}
+
+/// A set of modes which segments of data can be encoded in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SegmentModes(u8);
+
+impl SegmentModes {
+    /// The alphanumeric mode only.
+    pub const ALPHANUMERIC: SegmentModes = SegmentModes(SegmentMode::Alphanumeric.flag());
+    /// The byte mode only.
+    pub const BYTE: SegmentModes = SegmentModes(SegmentMode::Byte.flag());
+    /// No mode. Nothing but empty data can be encoded.
+    pub const EMPTY: SegmentModes = SegmentModes(0);
+    /// The Hanzi mode only.
+    pub const HANZI: SegmentModes = SegmentModes(SegmentMode::Hanzi.flag());
+    /// The kanji mode only.
+    pub const KANJI: SegmentModes = SegmentModes(SegmentMode::Kanji.flag());
+    /// The numeric mode only.
+    pub const NUMERIC: SegmentModes = SegmentModes(SegmentMode::Numeric.flag());
+
+    /// All of the modes which this build supports.
+    #[inline]
+    pub const fn all() -> SegmentModes {
+        let modes = SegmentModes(
+            SegmentModes::NUMERIC.0 | SegmentModes::ALPHANUMERIC.0 | SegmentModes::BYTE.0,
+        );
+
+        #[cfg(feature = "kanji")]
+        let modes = modes.with(SegmentMode::Kanji);
+
+        #[cfg(feature = "hanzi")]
+        let modes = modes.with(SegmentMode::Hanzi);
+
+        modes
+    }
+
+    /// Whether the mode is in this set.
+    #[inline]
+    pub const fn contains(self, mode: SegmentMode) -> bool {
+        self.0 & mode.flag() != 0
+    }
+
+    /// Add a mode to this set. The modes which don't encode data, `Eci` and `StructuredAppend`, are ignored.
+    #[inline]
+    pub const fn with(self, mode: SegmentMode) -> SegmentModes {
+        SegmentModes(self.0 | mode.flag())
+    }
+
+    /// Remove a mode from this set.
+    #[inline]
+    pub const fn without(self, mode: SegmentMode) -> SegmentModes {
+        SegmentModes(self.0 & !mode.flag())
+    }
+}
+
+impl Default for SegmentModes {
+    #[inline]
+    fn default() -> Self {
+        SegmentModes::all()
+    }
+}
+
+impl From<SegmentMode> for SegmentModes {
+    #[inline]
+    fn from(mode: SegmentMode) -> Self {
+        SegmentModes(mode.flag())
+    }
+}
+
+impl BitOr for SegmentModes {
+    type Output = SegmentModes;
+
+    #[inline]
+    fn bitor(self, rhs: SegmentModes) -> SegmentModes {
+        SegmentModes(self.0 | rhs.0)
+    }
+}
+
+/// A segment of character/binary/control data in a QR code symbol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    mode:      SegmentMode,
+    num_chars: usize,
+    data:      Vec<bool>,
+}
+
+impl Segment {
+    /// Creates a new segment with the given attributes and data.
+    ///
+    /// The character count (`num_chars`) must agree with the mode and the bit buffer length, but the constraint isn't checked.
+    #[inline]
+    pub fn new(mode: SegmentMode, num_chars: usize, data: Vec<bool>) -> Self {
+        Segment {
+            mode,
+            num_chars,
+            data,
+        }
+    }
+
+    /// Returns a segment representing the given binary data encoded in byte mode.
+    #[inline]
+    pub fn make_bytes(data: &[u8]) -> Self {
+        let mut bits = Vec::with_capacity(data.len() * 8);
+
+        for &b in data {
+            append_bits(&mut bits, u32::from(b), 8);
+        }
+
+        Segment::new(SegmentMode::Byte, data.len(), bits)
+    }
+
+    /// Returns a segment representing the given string of decimal digits encoded in numeric mode.
+    ///
+    /// Panics if the string contains non-digit characters.
+    pub fn make_numeric(text: &str) -> Self {
+        let mut bits = Vec::with_capacity(text.len() * 3 + (text.len() + 2) / 3);
+
+        for chunk in text.as_bytes().chunks(3) {
+            let val = chunk.iter().fold(0, |val, &b| {
+                assert!(b.is_ascii_digit(), "String contains non-numeric characters");
+
+                val * 10 + u32::from(b - b'0')
+            });
+
+            append_bits(&mut bits, val, chunk.len() as u8 * 3 + 1);
+        }
+
+        Segment::new(SegmentMode::Numeric, text.len(), bits)
+    }
+
+    /// Returns a segment representing the given text encoded in alphanumeric mode.
+    ///
+    /// Panics if the string contains characters which are not in the alphanumeric mode.
+    pub fn make_alphanumeric(text: &str) -> Self {
+        let mut bits = Vec::with_capacity(text.len() * 5 + (text.len() + 1) / 2);
+
+        for chunk in text.as_bytes().chunks(2) {
+            let val = chunk.iter().fold(0, |val, &b| {
+                let index = ALPHANUMERIC_CHARSET
+                    .iter()
+                    .position(|&c| c == char::from(b))
+                    .expect("String contains unencodable characters in alphanumeric mode");
+
+                val * 45 + index as u32
+            });
+
+            append_bits(&mut bits, val, chunk.len() as u8 * 5 + 1);
+        }
+
+        Segment::new(SegmentMode::Alphanumeric, text.len(), bits)
+    }
+
+    /// Returns a segment representing an ECI designator with the given assignment value.
+    ///
+    /// Panics if the value is not less than 1,000,000.
+    pub fn make_eci(assign_val: u32) -> Self {
+        let mut bits = Vec::with_capacity(24);
+
+        if assign_val < (1 << 7) {
+            append_bits(&mut bits, assign_val, 8);
+        } else if assign_val < (1 << 14) {
+            append_bits(&mut bits, 0b10, 2);
+            append_bits(&mut bits, assign_val, 14);
+        } else if assign_val < 1_000_000 {
+            append_bits(&mut bits, 0b110, 3);
+            append_bits(&mut bits, assign_val, 21);
+        } else {
+            panic!("ECI assignment value out of range");
+        }
+
+        Segment::new(SegmentMode::Eci, 0, bits)
+    }
+
+    /// Returns a segment representing a Structured Append header, which marks the symbol as the `index`-th (starting from 0) of `total` symbols. `parity` is the XOR of all bytes of the whole encoded data.
+    ///
+    /// Panics if `total` is greater than 16 or `index` is not less than `total`.
+    pub fn make_structured_append(index: u8, total: u8, parity: u8) -> Self {
+        assert!(total <= 16 && index < total, "Structured Append index out of range");
+
+        let mut bits = Vec::with_capacity(16);
+
+        append_bits(&mut bits, u32::from(index), 4);
+        append_bits(&mut bits, u32::from(total - 1), 4);
+        append_bits(&mut bits, u32::from(parity), 8);
+
+        Segment::new(SegmentMode::StructuredAppend, 0, bits)
+    }
+
+    /// Returns a segment representing the given text encoded in the kanji mode, or `None` if any of its characters is not in the kanji mode.
+    #[cfg(feature = "kanji")]
+    #[inline]
+    pub fn make_kanji(text: &str) -> Option<Self> {
+        Self::make_double_byte(SegmentMode::Kanji, text.chars().map(|c| KANJI_TABLE.value(c)))
+    }
+
+    /// Returns a segment representing the given text encoded in the Hanzi mode (GB2312 subset), or `None` if any of its characters is not in the Hanzi mode.
+    #[cfg(feature = "hanzi")]
+    #[inline]
+    pub fn make_hanzi(text: &str) -> Option<Self> {
+        Self::make_double_byte(SegmentMode::Hanzi, text.chars().map(|c| HANZI_TABLE.value(c)))
+    }
+
+    /// Returns a segment representing the given 13-bit values encoded in the kanji mode, or in the Hanzi mode if `mode` is `SegmentMode::Hanzi`. Returns `None` if any of the values is `None`.
+    #[cfg(any(feature = "kanji", feature = "hanzi"))]
+    pub(crate) fn make_double_byte(
+        mode: SegmentMode,
+        values: impl Iterator<Item = Option<u16>>,
+    ) -> Option<Self> {
+        let mut bits = Vec::new();
+        let mut num_chars = 0;
+
+        for val in values {
+            append_bits(&mut bits, u32::from(val?), 13);
+
+            num_chars += 1;
+        }
+
+        Some(Segment::new(mode, num_chars, bits))
+    }
+
+    /// Returns the mode indicator of this segment.
+    #[inline]
+    pub fn mode(&self) -> SegmentMode {
+        self.mode
+    }
+
+    /// Returns the character count field of this segment.
+    #[inline]
+    pub fn num_chars(&self) -> usize {
+        self.num_chars
+    }
+
+    /// Returns the data bits of this segment.
+    #[inline]
+    pub fn data(&self) -> &[bool] {
+        self.data.as_slice()
+    }
+
+    /// Converts this segment to a `QrSegment`. Returns `None` if `qrcodegen` does not support the mode of this segment.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn to_qr_segment(&self) -> Option<QrSegment> {
+        self.clone().into_qr_segment()
+    }
+
+    /// Converts this segment into a `QrSegment`. Returns `None` if `qrcodegen` does not support the mode of this segment.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn into_qr_segment(self) -> Option<QrSegment> {
+        self.mode.to_qr_segment_mode().map(|mode| QrSegment::new(mode, self.num_chars, self.data))
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<QrSegment> for Segment {
+    #[inline]
+    fn from(segment: QrSegment) -> Self {
+        Segment::new(segment.mode().into(), segment.num_chars(), segment.data().clone())
+    }
+}
+
+/// Appends the given number of low-order bits of the given value to the bits, from the most significant one.
+pub(crate) fn append_bits(bits: &mut Vec<bool>, val: u32, len: u8) {
+    debug_assert!(len <= 31 && val >> len == 0, "Value out of range");
+
+    bits.extend((0..len).rev().map(|i| (val >> i) & 1 != 0));
+}
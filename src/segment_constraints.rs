@@ -0,0 +1,57 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::SegmentMode;
+
+/// Requirements on the segments of a particular text or binary data, which are honored while everything else is optimized.
+///
+/// Positions are byte offsets in the text or in the data, and must be at the boundaries of code points. Segments which end at a forced boundary, including pinned segments, are exempt from the minimum run lengths of the cost model.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SegmentConstraints {
+    pub(crate) pins:   Vec<(Range<usize>, SegmentMode)>,
+    pub(crate) breaks: Vec<usize>,
+}
+
+impl SegmentConstraints {
+    /// Creates constraints which require nothing.
+    #[inline]
+    pub const fn new() -> Self {
+        SegmentConstraints {
+            pins: Vec::new(), breaks: Vec::new()
+        }
+    }
+
+    /// Pin the range to the mode, so that it is encoded as a segment of its own in the mode. The mode must be allowed by the options and supported by the chosen symbol, and pinned ranges must not overlap. An empty range pins nothing.
+    #[inline]
+    pub fn pin(mut self, range: Range<usize>, mode: SegmentMode) -> Self {
+        self.pins.push((range, mode));
+
+        self
+    }
+
+    /// Force a segment boundary at the position, even between code points in the same mode. The position must not be inside a pinned range. The start and the end of the data are always boundaries.
+    #[inline]
+    pub fn break_at(mut self, position: usize) -> Self {
+        self.breaks.push(position);
+
+        self
+    }
+
+    /// The pinned ranges along with their modes.
+    #[inline]
+    pub fn pins(&self) -> &[(Range<usize>, SegmentMode)] {
+        self.pins.as_slice()
+    }
+
+    /// The positions of the forced segment boundaries.
+    #[inline]
+    pub fn breaks(&self) -> &[usize] {
+        self.breaks.as_slice()
+    }
+
+    /// Whether nothing is required.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty() && self.breaks.is_empty()
+    }
+}
@@ -0,0 +1,174 @@
+use alloc::{vec, vec::Vec};
+
+#[cfg(feature = "std")]
+use qrcode_generator::QrSegment;
+
+use crate::{segment::append_bits, QrCodeEcc, Segment, SymbolVersion, Version};
+
+/// The optimized segments along with the symbol chosen for them and the bit accounting.
+#[derive(Debug, Clone)]
+pub struct SegmentPlan {
+    pub(crate) segments:      Vec<Segment>,
+    pub(crate) symbol:        SymbolVersion,
+    pub(crate) ecc:           QrCodeEcc,
+    pub(crate) data_bits:     usize,
+    pub(crate) capacity_bits: usize,
+    pub(crate) naive_bits:    Option<usize>,
+}
+
+impl SegmentPlan {
+    /// The optimized segments.
+    #[inline]
+    pub fn segments(&self) -> &[Segment] {
+        self.segments.as_slice()
+    }
+
+    /// Take the optimized segments.
+    #[inline]
+    pub fn into_segments(self) -> Vec<Segment> {
+        self.segments
+    }
+
+    /// Converts the optimized segments to `QrSegment`s. Returns `None` if any of them is in a mode which `qrcodegen` does not support.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn to_qr_segments(&self) -> Option<Vec<QrSegment>> {
+        self.segments.iter().map(Segment::to_qr_segment).collect()
+    }
+
+    /// Takes the optimized segments as `QrSegment`s. The segments in a mode which `qrcodegen` does not support are dropped, so the plan must be made without such modes.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub(crate) fn into_qr_segments(self) -> Vec<QrSegment> {
+        self.segments.into_iter().filter_map(Segment::into_qr_segment).collect()
+    }
+
+    /// Raises the error correction level to the highest one at which the segments still fit the chosen symbol.
+    #[inline]
+    pub(crate) fn boost_ecc(&mut self) {
+        self.set_ecc(self.highest_ecc());
+    }
+
+    /// Returns the highest error correction level, not lower than the current one, at which the segments still fit the chosen symbol.
+    pub(crate) fn highest_ecc(&self) -> QrCodeEcc {
+        let mut highest_ecc = self.ecc;
+
+        for ecc in [QrCodeEcc::Medium, QrCodeEcc::Quartile, QrCodeEcc::High] {
+            if ecc <= self.ecc {
+                continue;
+            }
+
+            if let Some(capacity_bits) = self.symbol.num_data_bits(ecc) {
+                if self.data_bits <= capacity_bits {
+                    highest_ecc = self.symbol.supported_ecc(ecc).unwrap();
+                }
+            }
+        }
+
+        highest_ecc
+    }
+
+    /// Changes the error correction level to the given one, at which the segments have to fit the chosen symbol.
+    #[inline]
+    pub(crate) fn set_ecc(&mut self, ecc: QrCodeEcc) {
+        self.ecc = ecc;
+        self.capacity_bits = self.symbol.num_data_bits(ecc).unwrap();
+    }
+
+    /// The smallest symbol which can hold the segments.
+    #[inline]
+    pub fn symbol(&self) -> SymbolVersion {
+        self.symbol
+    }
+
+    /// The QR code version of the chosen symbol. Returns `None` if it is not a QR code symbol.
+    #[inline]
+    pub fn version(&self) -> Option<Version> {
+        self.symbol.qr_version()
+    }
+
+    /// The error correction level of the chosen symbol. It can be higher than the given one if `Options::boost_ecc` is set, or if the symbol does not have the given level.
+    #[inline]
+    pub fn ecc(&self) -> QrCodeEcc {
+        self.ecc
+    }
+
+    /// The number of bits used by the segments, including their headers.
+    #[inline]
+    pub fn data_bits(&self) -> usize {
+        self.data_bits
+    }
+
+    /// The number of data bits which can be stored in the chosen symbol at the chosen error correction level.
+    #[inline]
+    pub fn capacity_bits(&self) -> usize {
+        self.capacity_bits
+    }
+
+    /// The number of data bits which are still available in the chosen symbol.
+    #[inline]
+    pub fn spare_bits(&self) -> usize {
+        self.capacity_bits - self.data_bits
+    }
+
+    /// The number of bits needed to encode the same data as a single byte-mode segment in the chosen symbol, for comparison. Returns `None` if the symbol cannot hold such a segment, which happens to M1 and M2.
+    #[inline]
+    pub fn naive_bits(&self) -> Option<usize> {
+        self.naive_bits
+    }
+
+    /// Returns the data codewords of the chosen symbol, including the terminator and the padding.
+    ///
+    /// For a QR code symbol, they can be passed to `QrCode::encode_codewords` along with the chosen version and the chosen error correction level, which is the way to draw the segments that `qrcodegen` does not support. For M1 and M3, the last data codeword is 4 bits long, which is stored in the upper half of the last byte.
+    pub fn to_data_codewords(&self) -> Vec<u8> {
+        let mut bits = Vec::with_capacity(self.capacity_bits);
+
+        for seg in self.segments.iter() {
+            let mode = seg.mode();
+
+            append_bits(
+                &mut bits,
+                self.symbol.mode_indicator(mode),
+                self.symbol.mode_indicator_bits(),
+            );
+
+            if mode.num_subset_bits() > 0 {
+                // the subset indicator of GB 2312
+                append_bits(&mut bits, 1, mode.num_subset_bits());
+            }
+
+            append_bits(
+                &mut bits,
+                seg.num_chars() as u32,
+                self.symbol.num_char_count_bits(mode).unwrap(),
+            );
+            bits.extend_from_slice(seg.data());
+        }
+
+        // Add terminator and pad up to a byte if applicable
+        let num_zero_bits = (self.capacity_bits - bits.len()).min(self.symbol.terminator_bits());
+        append_bits(&mut bits, 0, num_zero_bits as u8);
+        let num_zero_bits = (bits.len().wrapping_neg() & 7).min(self.capacity_bits - bits.len());
+        append_bits(&mut bits, 0, num_zero_bits as u8);
+
+        // Pad with alternating bytes until data capacity is reached
+        for &pad_byte in [0xEC, 0x11].iter().cycle() {
+            if bits.len() + 8 > self.capacity_bits {
+                break;
+            }
+            append_bits(&mut bits, pad_byte, 8);
+        }
+
+        // The last 4-bit data codeword of M1 and M3 is padded with zeros
+        let num_zero_bits = self.capacity_bits - bits.len();
+        append_bits(&mut bits, 0, num_zero_bits as u8);
+
+        // Pack bits into bytes in big endian
+        let mut data_codewords = vec![0u8; (bits.len() + 7) / 8];
+        for (i, &bit) in bits.iter().enumerate() {
+            data_codewords[i >> 3] |= u8::from(bit) << (7 - (i & 7));
+        }
+
+        data_codewords
+    }
+}
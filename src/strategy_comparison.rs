@@ -0,0 +1,36 @@
+use crate::SegmentPlan;
+
+/// The plans of the same data by the optimal strategy and by the mode selection of ISO/IEC 18004 Annex J.
+#[derive(Debug, Clone)]
+pub struct StrategyComparison {
+    pub(crate) optimal: SegmentPlan,
+    pub(crate) annex_j: SegmentPlan,
+}
+
+impl StrategyComparison {
+    /// The plan of `Strategy::Optimal`.
+    #[inline]
+    pub fn optimal(&self) -> &SegmentPlan {
+        &self.optimal
+    }
+
+    /// The plan of `Strategy::AnnexJ`.
+    #[inline]
+    pub fn annex_j(&self) -> &SegmentPlan {
+        &self.annex_j
+    }
+
+    /// The number of bits which the Annex J segments use more than the optimal segments, including their headers. It is never negative unless the cost model of the options trades bits for fewer or longer segments.
+    ///
+    /// The plans may choose different symbols, whose character count fields can differ in length.
+    #[inline]
+    pub fn bit_difference(&self) -> isize {
+        self.annex_j.data_bits as isize - self.optimal.data_bits as isize
+    }
+
+    /// Whether both strategies chose the same symbol and the same segments.
+    #[inline]
+    pub fn is_identical(&self) -> bool {
+        self.optimal.symbol == self.annex_j.symbol && self.optimal.segments == self.annex_j.segments
+    }
+}
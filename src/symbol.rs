@@ -0,0 +1,406 @@
+use core::fmt::{self, Display, Formatter};
+
+use crate::{qr_segment_advanced::get_num_data_codewords, QrCodeEcc, SegmentMode, Version};
+
+/// A number between 1 and 4 (inclusive), which means the Micro QR code version M1 to M4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MicroVersion(u8);
+
+impl MicroVersion {
+    /// The maximum version number (M4) supported in the Micro QR code standard.
+    pub const MAX: MicroVersion = MicroVersion(4);
+    /// The minimum version number (M1) supported in the Micro QR code standard.
+    pub const MIN: MicroVersion = MicroVersion(1);
+
+    /// Creates a version object from the given number.
+    ///
+    /// Panics if the number is outside the range [1, 4].
+    #[inline]
+    pub const fn new(ver: u8) -> Self {
+        assert!(
+            ver >= MicroVersion::MIN.0 && ver <= MicroVersion::MAX.0,
+            "Version number out of range"
+        );
+
+        MicroVersion(ver)
+    }
+
+    /// Returns the value, which is in the range [1, 4].
+    #[inline]
+    pub const fn value(self) -> u8 {
+        self.0
+    }
+}
+
+// The heights and the widths of the rMQR code symbols, in the order of ISO/IEC 23941.
+static RMQR_SIZES: [(u8, u8); 32] = [
+    (7, 43),
+    (7, 59),
+    (7, 77),
+    (7, 99),
+    (7, 139),
+    (9, 43),
+    (9, 59),
+    (9, 77),
+    (9, 99),
+    (9, 139),
+    (11, 27),
+    (11, 43),
+    (11, 59),
+    (11, 77),
+    (11, 99),
+    (11, 139),
+    (13, 27),
+    (13, 43),
+    (13, 59),
+    (13, 77),
+    (13, 99),
+    (13, 139),
+    (15, 43),
+    (15, 59),
+    (15, 77),
+    (15, 99),
+    (15, 139),
+    (17, 43),
+    (17, 59),
+    (17, 77),
+    (17, 99),
+    (17, 139),
+];
+
+// The numbers of data codewords of the rMQR code symbols at the M and H levels.
+static RMQR_NUM_DATA_CODEWORDS: [[u8; 32]; 2] = [
+    [
+        6, 12, 20, 28, 44, 12, 21, 31, 42, 63, 7, 19, 31, 43, 57, 84, 12, 27, 38, 53, 73, 106, 33,
+        48, 67, 88, 127, 39, 56, 78, 100, 152,
+    ], // Medium
+    [
+        3, 7, 10, 14, 24, 7, 11, 17, 22, 33, 5, 11, 15, 23, 29, 42, 7, 13, 20, 29, 35, 54, 15, 26,
+        31, 48, 69, 21, 28, 38, 56, 76,
+    ], // High
+];
+
+// The bit widths of the character count fields of the rMQR code symbols in the numeric, alphanumeric, byte and kanji modes.
+static RMQR_CHAR_COUNT_BITS: [[u8; 32]; 4] = [
+    [
+        4, 5, 6, 7, 7, 5, 6, 7, 7, 8, 4, 6, 7, 7, 8, 8, 5, 6, 7, 7, 8, 8, 7, 7, 8, 8, 9, 7, 8, 8,
+        8, 9,
+    ], // Numeric
+    [
+        3, 5, 5, 6, 6, 5, 5, 6, 6, 7, 4, 5, 6, 6, 7, 7, 5, 6, 6, 7, 7, 8, 6, 7, 7, 7, 8, 6, 7, 7,
+        8, 8,
+    ], // Alphanumeric
+    [
+        3, 4, 5, 5, 6, 4, 5, 5, 6, 6, 3, 5, 5, 6, 6, 7, 4, 5, 6, 6, 7, 7, 6, 6, 7, 7, 7, 6, 6, 7,
+        7, 8,
+    ], // Byte
+    [
+        2, 3, 4, 5, 5, 3, 4, 5, 5, 6, 2, 4, 5, 5, 6, 6, 3, 5, 5, 6, 6, 7, 5, 5, 6, 6, 7, 5, 6, 6,
+        6, 7,
+    ], // Kanji
+];
+
+/// One of the 32 sizes of the rMQR code (R7x43 to R17x139).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RmqrVersion(u8);
+
+impl RmqrVersion {
+    /// Creates a version object from the given height and width.
+    ///
+    /// Panics if they are not the size of an rMQR code symbol.
+    #[inline]
+    pub fn new(height: u8, width: u8) -> Self {
+        let index = RMQR_SIZES
+            .iter()
+            .position(|&size| size == (height, width))
+            .expect("Invalid rMQR code size");
+
+        RmqrVersion(index as u8)
+    }
+
+    /// Returns all of the rMQR code sizes, in the order of ISO/IEC 23941.
+    #[inline]
+    pub fn all() -> impl Iterator<Item = RmqrVersion> {
+        (0..RMQR_SIZES.len() as u8).map(RmqrVersion)
+    }
+
+    /// Returns the number of rows of modules, which is in the range [7, 17].
+    #[inline]
+    pub fn height(self) -> u8 {
+        RMQR_SIZES[usize::from(self.0)].0
+    }
+
+    /// Returns the number of columns of modules, which is in the range [27, 139].
+    #[inline]
+    pub fn width(self) -> u8 {
+        RMQR_SIZES[usize::from(self.0)].1
+    }
+}
+
+impl Display for RmqrVersion {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        f.write_fmt(format_args!("R{}x{}", self.height(), self.width()))
+    }
+}
+
+/// A symbol which segments can be placed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolVersion {
+    /// A Micro QR code symbol.
+    Micro(MicroVersion),
+    /// A QR code (Model 2) symbol.
+    Qr(Version),
+    /// An rMQR code symbol.
+    Rmqr(RmqrVersion),
+}
+
+impl SymbolVersion {
+    /// Returns the QR code version if this is a QR code symbol.
+    #[inline]
+    pub fn qr_version(self) -> Option<Version> {
+        match self {
+            SymbolVersion::Qr(version) => Some(version),
+            _ => None,
+        }
+    }
+
+    /// Returns the Micro QR code version if this is a Micro QR code symbol.
+    #[inline]
+    pub fn micro_version(self) -> Option<MicroVersion> {
+        match self {
+            SymbolVersion::Micro(version) => Some(version),
+            _ => None,
+        }
+    }
+
+    /// Returns the rMQR code size if this is an rMQR code symbol.
+    #[inline]
+    pub fn rmqr_version(self) -> Option<RmqrVersion> {
+        match self {
+            SymbolVersion::Rmqr(version) => Some(version),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of columns of modules of this symbol.
+    #[inline]
+    pub fn width(self) -> u32 {
+        match self {
+            SymbolVersion::Micro(version) => u32::from(version.value()) * 2 + 9,
+            SymbolVersion::Qr(version) => u32::from(version.value()) * 4 + 17,
+            SymbolVersion::Rmqr(version) => u32::from(version.width()),
+        }
+    }
+
+    /// Returns the number of rows of modules of this symbol.
+    #[inline]
+    pub fn height(self) -> u32 {
+        match self {
+            SymbolVersion::Rmqr(version) => u32::from(version.height()),
+            _ => self.width(),
+        }
+    }
+
+    /// Returns the bit width of the mode indicator.
+    #[inline]
+    pub(crate) fn mode_indicator_bits(self) -> u8 {
+        match self {
+            SymbolVersion::Micro(version) => version.value() - 1,
+            SymbolVersion::Qr(_) => 4,
+            SymbolVersion::Rmqr(_) => 3,
+        }
+    }
+
+    /// Returns the mode indicator of the given mode.
+    #[inline]
+    pub(crate) fn mode_indicator(self, mode: SegmentMode) -> u32 {
+        match self {
+            SymbolVersion::Micro(_) => match mode {
+                SegmentMode::Numeric => 0,
+                SegmentMode::Alphanumeric => 1,
+                SegmentMode::Byte => 2,
+                SegmentMode::Kanji => 3,
+                _ => unreachable!(),
+            },
+            SymbolVersion::Qr(_) => mode.mode_bits(),
+            SymbolVersion::Rmqr(_) => match mode {
+                SegmentMode::Numeric => 1,
+                SegmentMode::Alphanumeric => 2,
+                SegmentMode::Byte => 3,
+                SegmentMode::Kanji => 4,
+                SegmentMode::Eci => 7,
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    /// Returns the mode of the given mode indicator, or `None` if this symbol does not support the mode.
+    pub(crate) fn mode_from_indicator(self, indicator: u32) -> Option<SegmentMode> {
+        let mode = match self {
+            SymbolVersion::Micro(_) => match indicator {
+                0 => SegmentMode::Numeric,
+                1 => SegmentMode::Alphanumeric,
+                2 => SegmentMode::Byte,
+                3 => SegmentMode::Kanji,
+                _ => return None,
+            },
+            SymbolVersion::Qr(_) => match indicator {
+                0x1 => SegmentMode::Numeric,
+                0x2 => SegmentMode::Alphanumeric,
+                0x4 => SegmentMode::Byte,
+                0x8 => SegmentMode::Kanji,
+                0xD => SegmentMode::Hanzi,
+                0x7 => SegmentMode::Eci,
+                0x3 => SegmentMode::StructuredAppend,
+                _ => return None,
+            },
+            SymbolVersion::Rmqr(_) => match indicator {
+                1 => SegmentMode::Numeric,
+                2 => SegmentMode::Alphanumeric,
+                3 => SegmentMode::Byte,
+                4 => SegmentMode::Kanji,
+                7 => SegmentMode::Eci,
+                _ => return None,
+            },
+        };
+
+        match mode {
+            SegmentMode::Eci | SegmentMode::StructuredAppend => Some(mode),
+            _ => self.num_char_count_bits(mode).map(|_| mode),
+        }
+    }
+
+    /// Returns the bit width of the character count field for a segment in the given mode, or `None` if this symbol does not support the mode.
+    #[inline]
+    pub(crate) fn num_char_count_bits(self, mode: SegmentMode) -> Option<u8> {
+        match self {
+            SymbolVersion::Micro(version) => {
+                let i = usize::from(version.value() - 1);
+
+                let bits = match mode {
+                    SegmentMode::Numeric => [3, 4, 5, 6][i],
+                    SegmentMode::Alphanumeric => [0, 3, 4, 5][i],
+                    SegmentMode::Byte => [0, 0, 4, 5][i],
+                    SegmentMode::Kanji => [0, 0, 3, 4][i],
+                    _ => 0,
+                };
+
+                if bits > 0 {
+                    Some(bits)
+                } else {
+                    None
+                }
+            },
+            SymbolVersion::Qr(version) => Some(mode.num_char_count_bits(version)),
+            SymbolVersion::Rmqr(version) => {
+                let i = usize::from(version.0);
+
+                match mode {
+                    SegmentMode::Numeric => Some(RMQR_CHAR_COUNT_BITS[0][i]),
+                    SegmentMode::Alphanumeric => Some(RMQR_CHAR_COUNT_BITS[1][i]),
+                    SegmentMode::Byte => Some(RMQR_CHAR_COUNT_BITS[2][i]),
+                    SegmentMode::Kanji => Some(RMQR_CHAR_COUNT_BITS[3][i]),
+                    SegmentMode::Eci => Some(0),
+                    _ => None,
+                }
+            },
+        }
+    }
+
+    /// Returns whether this symbol supports ECI designators.
+    #[inline]
+    pub(crate) fn supports_eci(self) -> bool {
+        !matches!(self, SymbolVersion::Micro(_))
+    }
+
+    /// Returns the error correction level which this symbol actually uses for the given one, or `None` if this symbol does not support the level.
+    ///
+    /// rMQR code symbols only have the M and H levels, so `Low` is raised to M and `Quartile` is raised to H for them.
+    #[inline]
+    pub fn supported_ecc(self, ecc: QrCodeEcc) -> Option<QrCodeEcc> {
+        match self {
+            SymbolVersion::Micro(_) => self.num_data_bits(ecc).map(|_| ecc),
+            SymbolVersion::Qr(_) => Some(ecc),
+            SymbolVersion::Rmqr(_) => match ecc {
+                QrCodeEcc::Low | QrCodeEcc::Medium => Some(QrCodeEcc::Medium),
+                QrCodeEcc::Quartile | QrCodeEcc::High => Some(QrCodeEcc::High),
+            },
+        }
+    }
+
+    /// Returns the number of data bits which can be stored in this symbol at the given error correction level, or `None` if this symbol does not support the level.
+    ///
+    /// The last data codewords of M1 and M3 are 4 bits long. For rMQR code symbols, the level is raised as `supported_ecc` does.
+    pub(crate) fn num_data_bits(self, ecc: QrCodeEcc) -> Option<usize> {
+        match self {
+            SymbolVersion::Micro(version) => {
+                let bits = match (version.value(), ecc) {
+                    // M1 only has error detection
+                    (1, QrCodeEcc::Low) => 20,
+                    (2, QrCodeEcc::Low) => 40,
+                    (2, QrCodeEcc::Medium) => 32,
+                    (3, QrCodeEcc::Low) => 84,
+                    (3, QrCodeEcc::Medium) => 68,
+                    (4, QrCodeEcc::Low) => 128,
+                    (4, QrCodeEcc::Medium) => 112,
+                    (4, QrCodeEcc::Quartile) => 80,
+                    _ => return None,
+                };
+
+                Some(bits)
+            },
+            SymbolVersion::Qr(version) => Some(get_num_data_codewords(version, ecc) * 8),
+            SymbolVersion::Rmqr(version) => {
+                let level = match ecc {
+                    QrCodeEcc::Low | QrCodeEcc::Medium => 0,
+                    QrCodeEcc::Quartile | QrCodeEcc::High => 1,
+                };
+
+                Some(usize::from(RMQR_NUM_DATA_CODEWORDS[level][usize::from(version.0)]) * 8)
+            },
+        }
+    }
+
+    /// Returns the bit width of the terminator.
+    #[inline]
+    pub(crate) fn terminator_bits(self) -> usize {
+        match self {
+            SymbolVersion::Micro(version) => usize::from(version.value()) * 2 + 1,
+            SymbolVersion::Qr(_) => 4,
+            SymbolVersion::Rmqr(_) => 3,
+        }
+    }
+}
+
+impl From<Version> for SymbolVersion {
+    #[inline]
+    fn from(version: Version) -> Self {
+        SymbolVersion::Qr(version)
+    }
+}
+
+impl From<MicroVersion> for SymbolVersion {
+    #[inline]
+    fn from(version: MicroVersion) -> Self {
+        SymbolVersion::Micro(version)
+    }
+}
+
+impl From<RmqrVersion> for SymbolVersion {
+    #[inline]
+    fn from(version: RmqrVersion) -> Self {
+        SymbolVersion::Rmqr(version)
+    }
+}
+
+impl Display for SymbolVersion {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            SymbolVersion::Micro(version) => f.write_fmt(format_args!("M{}", version.value())),
+            SymbolVersion::Qr(version) => f.write_fmt(format_args!("{}", version.value())),
+            SymbolVersion::Rmqr(version) => Display::fmt(version, f),
+        }
+    }
+}
@@ -8,8 +8,15 @@ use std::fs;
 #[cfg(feature = "test-image")]
 use std::path::Path;
 
-use qrcode_generator::QrCodeEcc;
-use qrcode_segments_optimizer::models::Email;
+use qrcode_generator::{
+    qrcodegen::{QrCode, Version},
+    QrCodeEcc, QrSegment,
+};
+use qrcode_segments_optimizer::{
+    models::Email, Charset, Charsets, CostModel, DecodeError, EciPolicy, MarkedText, MicroVersion,
+    OptimizeError, Optimizer, Options, Profile, RmqrVersion, Segment, SegmentConstraints,
+    SegmentMode, SegmentModes, Strategy, SymbolVersion, Target,
+};
 use url::Url;
 
 #[cfg(feature = "test-image")]
@@ -51,6 +58,1807 @@ fn optimize_email() {
     assert!(optimized_matrix.len() < naive_matrix.len());
 }
 
+#[test]
+fn version_range() {
+    let text = "HELLO 12345";
+    let ecc = QrCodeEcc::Low;
+
+    let options = Options::new().version_range(Version::new(3), Version::new(7));
+
+    let segments =
+        qrcode_segments_optimizer::make_segments_from_str_with_options(text, ecc, &options)
+            .unwrap();
+
+    let qr = QrCode::encode_segments_advanced(
+        &segments,
+        ecc,
+        options.min_version,
+        options.max_version,
+        None,
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(Version::new(3), qr.version());
+
+    let options = Options::new().version_range(Version::new(1), Version::new(1));
+
+    assert_eq!(
+        Some(OptimizeError::DataTooLong {
+            required_bits: 275, capacity_bits: 152
+        }),
+        qrcode_segments_optimizer::make_segments_from_str_with_options(
+            "https://magiclen.org/path/to/12345",
+            ecc,
+            &options
+        )
+        .err()
+    );
+
+    let options = Options::new().version_range(Version::new(7), Version::new(3));
+
+    assert_eq!(
+        Some(OptimizeError::InvalidVersionRange {
+            min_version: Version::new(7),
+            max_version: Version::new(3),
+        }),
+        qrcode_segments_optimizer::make_segments_from_str_with_options(text, ecc, &options).err()
+    );
+}
+
+#[test]
+fn plan_url() {
+    let url = Url::parse("https://magiclen.org/path/to/12345").unwrap();
+    let ecc = QrCodeEcc::Low;
+
+    let plan =
+        qrcode_segments_optimizer::plan_segments_from_url(&url, ecc, &Options::new()).unwrap();
+
+    let qr = QrCode::encode_segments(&plan.to_qr_segments().unwrap(), ecc).unwrap();
+
+    assert_eq!(Some(qr.version()), plan.version());
+    assert!(plan.data_bits() <= plan.capacity_bits());
+    assert_eq!(plan.capacity_bits() - plan.data_bits(), plan.spare_bits());
+    assert!(plan.data_bits() < plan.naive_bits().unwrap());
+    // a single byte-mode segment: 4-bit mode indicator + 8-bit length + 34 bytes
+    assert_eq!(Some(4 + 8 + 34 * 8), plan.naive_bits());
+}
+
+#[test]
+fn optimize_bytes() {
+    let mut data = vec![0x00, 0xFF, 0x80, 0x7F];
+    data.extend_from_slice(b"012345678901234567890123456789");
+    data.extend_from_slice(&[0xC0, 0xFE]);
+
+    let ecc = QrCodeEcc::Medium;
+
+    let plan =
+        qrcode_segments_optimizer::plan_segments_from_bytes(&data, ecc, &Options::new()).unwrap();
+
+    assert!(plan.data_bits() < plan.naive_bits().unwrap());
+    assert!(plan.segments().iter().any(|segment| segment.mode() == SegmentMode::Numeric));
+
+    let naive_qr = QrCode::encode_binary(&data, ecc).unwrap();
+    let optimized_qr = QrCode::encode_segments(&plan.to_qr_segments().unwrap(), ecc).unwrap();
+
+    assert!(optimized_qr.version() <= naive_qr.version());
+}
+
+#[test]
+fn eci() {
+    let text = "HELLO WORLD 12345 café";
+    let ecc = QrCodeEcc::Low;
+
+    let never_plan =
+        qrcode_segments_optimizer::plan_segments_from_str(text, ecc, &Options::new()).unwrap();
+
+    assert!(never_plan.segments().iter().all(|segment| segment.mode() != SegmentMode::Eci));
+
+    let prefix_plan = qrcode_segments_optimizer::plan_segments_from_str(
+        text,
+        ecc,
+        &Options::new().eci(EciPolicy::Prefix),
+    )
+    .unwrap();
+
+    assert_eq!(SegmentMode::Eci, prefix_plan.segments()[0].mode());
+    assert_eq!(never_plan.data_bits() + 12, prefix_plan.data_bits());
+
+    let before_non_ascii_plan = qrcode_segments_optimizer::plan_segments_from_str(
+        text,
+        ecc,
+        &Options::new().eci(EciPolicy::BeforeNonAscii),
+    )
+    .unwrap();
+
+    let segments = before_non_ascii_plan.segments();
+
+    let eci_index = segments.iter().position(|segment| segment.mode() == SegmentMode::Eci).unwrap();
+
+    assert_eq!(SegmentMode::Byte, segments[eci_index + 1].mode());
+    assert!(segments[..eci_index].iter().all(|segment| segment.mode() != SegmentMode::Byte));
+    assert_eq!(never_plan.data_bits() + 12, before_non_ascii_plan.data_bits());
+
+    let qr =
+        QrCode::encode_segments(&before_non_ascii_plan.to_qr_segments().unwrap(), ecc).unwrap();
+
+    assert_eq!(before_non_ascii_plan.version(), Some(qr.version()));
+
+    let ascii_plan = qrcode_segments_optimizer::plan_segments_from_str(
+        "HELLO WORLD 12345 cafe",
+        ecc,
+        &Options::new().eci(EciPolicy::BeforeNonAscii),
+    )
+    .unwrap();
+
+    assert!(ascii_plan.segments().iter().all(|segment| segment.mode() != SegmentMode::Eci));
+}
+
+fn eci_assignments(segments: &[Segment]) -> Vec<u32> {
+    segments
+        .iter()
+        .filter(|segment| segment.mode() == SegmentMode::Eci)
+        .map(|segment| segment.data().iter().fold(0, |value, &bit| (value << 1) | bit as u32))
+        .collect()
+}
+
+#[test]
+fn charsets() {
+    let text = "Crème brûlée à la carte, façade déjà vue";
+    let ecc = QrCodeEcc::Low;
+
+    let options = Options::new().eci(EciPolicy::BeforeNonAscii);
+
+    let utf8_plan = qrcode_segments_optimizer::plan_segments_from_str(text, ecc, &options).unwrap();
+
+    assert_eq!(vec![Charset::Utf8.eci_assignment()], eci_assignments(utf8_plan.segments()));
+
+    let latin1_plan = qrcode_segments_optimizer::plan_segments_from_str(
+        text,
+        ecc,
+        &options.charsets(Charsets::UTF_8 | Charsets::ISO_8859_1),
+    )
+    .unwrap();
+
+    assert_eq!(vec![Charset::Iso8859_1.eci_assignment()], eci_assignments(latin1_plan.segments()));
+    assert!(latin1_plan.data_bits() < utf8_plan.data_bits());
+
+    // ISO-8859-1 cannot encode the euro sign, so UTF-8 takes over when it is cheaper to switch
+    let mixed_plan = qrcode_segments_optimizer::plan_segments_from_str(
+        "déjà vu, très élégant café crème €€€€€€",
+        ecc,
+        &Options::new().eci(EciPolicy::Prefix).charsets(Charsets::all()),
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec![Charset::Iso8859_1.eci_assignment(), Charset::Utf8.eci_assignment()],
+        eci_assignments(mixed_plan.segments())
+    );
+}
+
+#[cfg(feature = "kanji")]
+#[test]
+fn charsets_shift_jis() {
+    let text = "ｶﾀｶﾅ ﾃｷｽﾄ ﾃﾞｽ";
+    let ecc = QrCodeEcc::Low;
+
+    let options = Options::new().eci(EciPolicy::BeforeNonAscii);
+
+    let utf8_plan = qrcode_segments_optimizer::plan_segments_from_str(text, ecc, &options).unwrap();
+
+    let sjis_plan = qrcode_segments_optimizer::plan_segments_from_str(
+        text,
+        ecc,
+        &options.charsets(Charsets::all()),
+    )
+    .unwrap();
+
+    assert_eq!(vec![Charset::ShiftJis.eci_assignment()], eci_assignments(sjis_plan.segments()));
+    assert!(sjis_plan.data_bits() < utf8_plan.data_bits());
+}
+
+#[cfg(feature = "hanzi")]
+#[test]
+fn hanzi() {
+    let text = "中文二维码测试，汉字模式";
+    let ecc = QrCodeEcc::Medium;
+
+    // the Hanzi mode is opt-in
+    let plan =
+        qrcode_segments_optimizer::plan_segments_from_str(text, ecc, &Options::new()).unwrap();
+
+    assert!(plan.segments().iter().all(|segment| segment.mode() != SegmentMode::Hanzi));
+
+    let plan = qrcode_segments_optimizer::plan_segments_from_str(
+        text,
+        ecc,
+        &Options::new().modes(SegmentModes::all()),
+    )
+    .unwrap();
+
+    assert!(plan.segments().iter().any(|segment| segment.mode() == SegmentMode::Hanzi));
+    assert!(plan.to_qr_segments().is_none());
+    assert!(plan.data_bits() < plan.naive_bits().unwrap());
+
+    let data_codewords = plan.to_data_codewords();
+
+    assert_eq!(plan.capacity_bits() / 8, data_codewords.len());
+    // the mode indicator of the Hanzi mode and the subset indicator of GB 2312
+    assert_eq!(0b1101_0001, data_codewords[0]);
+
+    let qr = QrCode::encode_codewords(plan.version().unwrap(), ecc, &data_codewords, None);
+
+    assert_eq!(plan.version(), Some(qr.version()));
+
+    // the functions which return `QrSegment`s do not use the Hanzi mode
+    let segments = qrcode_segments_optimizer::make_segments_from_str(text, ecc).unwrap();
+
+    QrCode::encode_segments(&segments, ecc).unwrap();
+}
+
+#[test]
+fn modes() {
+    let ecc = QrCodeEcc::Low;
+
+    let plan_modes = |text: &str, modes: SegmentModes| {
+        qrcode_segments_optimizer::plan_segments_from_str(text, ecc, &Options::new().modes(modes))
+            .map(|plan| plan.segments().iter().map(Segment::mode).collect::<Vec<SegmentMode>>())
+    };
+
+    let text = "HTTPS://MAGICLEN.ORG/12345678901234567890";
+
+    assert_eq!(
+        Ok(vec![SegmentMode::Alphanumeric, SegmentMode::Numeric]),
+        plan_modes(text, SegmentModes::all())
+    );
+    assert_eq!(Ok(vec![SegmentMode::Alphanumeric]), plan_modes(text, SegmentModes::ALPHANUMERIC));
+    assert_eq!(
+        Ok(vec![SegmentMode::Byte, SegmentMode::Numeric]),
+        plan_modes(text, SegmentModes::BYTE | SegmentModes::NUMERIC)
+    );
+    assert_eq!(
+        Ok(vec![SegmentMode::Byte]),
+        plan_modes(
+            text,
+            SegmentModes::all().without(SegmentMode::Alphanumeric).without(SegmentMode::Numeric)
+        )
+    );
+    assert_eq!(Err(OptimizeError::InvalidInput), plan_modes(text, SegmentModes::NUMERIC));
+    assert_eq!(Ok(vec![]), plan_modes("", SegmentModes::EMPTY));
+
+    #[cfg(feature = "kanji")]
+    {
+        let text = "日本語の文章";
+
+        assert!(plan_modes(text, SegmentModes::all()).unwrap().contains(&SegmentMode::Kanji));
+        assert!(!plan_modes(text, SegmentModes::all().without(SegmentMode::Kanji))
+            .unwrap()
+            .contains(&SegmentMode::Kanji));
+        assert_eq!(
+            Err(OptimizeError::InvalidInput),
+            plan_modes("日本語 text", SegmentModes::KANJI)
+        );
+
+        // ASCII characters are never in the kanji mode, not even the reverse solidus which is in the kanji table
+        assert_eq!(
+            Ok(vec![SegmentMode::Kanji, SegmentMode::Byte, SegmentMode::Kanji]),
+            plan_modes("日本\\日本語", SegmentModes::all())
+        );
+        assert_eq!(Err(OptimizeError::InvalidInput), plan_modes("\\", SegmentModes::KANJI));
+        assert_eq!(Err(OptimizeError::InvalidInput), plan_modes("~", SegmentModes::KANJI));
+    }
+
+    assert!(Options::new().modes.contains(SegmentMode::Byte));
+    assert!(!SegmentModes::all().contains(SegmentMode::Eci));
+    assert_eq!(SegmentModes::EMPTY, SegmentModes::EMPTY.with(SegmentMode::StructuredAppend));
+}
+
+#[test]
+fn profiles() {
+    let ecc = QrCodeEcc::Low;
+    let text = "HELLO WORLD 0123456789012345 hello world HELLO WORLD 0123456789012345 hello world";
+
+    let plan =
+        qrcode_segments_optimizer::plan_segments_from_str(text, ecc, &Options::new()).unwrap();
+
+    assert!(plan.segments().len() > 4);
+
+    let plan =
+        qrcode_segments_optimizer::plan_segments_from_str(text, ecc, &Profile::Conservative.into())
+            .unwrap();
+
+    assert!(plan.segments().len() <= 4);
+
+    // the profiles keep the other options
+    let options =
+        Options::new().version_range(Version::new(2), Version::new(10)).profile(Profile::StrictIso);
+
+    assert_eq!(Version::new(2), options.min_version);
+    assert_eq!(EciPolicy::BeforeNonAscii, options.eci);
+    assert_eq!(Charset::Iso8859_1, options.implicit_charset);
+
+    // byte-mode data needs an ECI designator unless it is ASCII
+    let plan = qrcode_segments_optimizer::plan_segments_from_str("café", ecc, &options).unwrap();
+
+    assert_eq!(vec![Charset::Iso8859_1.eci_assignment()], eci_assignments(plan.segments()));
+
+    let plan = qrcode_segments_optimizer::plan_segments_from_str("cafe", ecc, &options).unwrap();
+
+    assert!(eci_assignments(plan.segments()).is_empty());
+
+    // the profiles apply to the URL and email front-ends in the same way
+    let url = Url::parse("https://magiclen.org/path/to/12345/abc/67890/def").unwrap();
+    let email = Email::from_str("len12345@email.abcde67890.example.org").unwrap();
+
+    for profile in [Profile::StrictIso, Profile::Conservative, Profile::JapaneseDomestic] {
+        let segments = qrcode_segments_optimizer::make_segments_from_url_with_options(
+            &url,
+            ecc,
+            &profile.options(),
+        )
+        .unwrap();
+
+        QrCode::encode_segments(&segments, ecc).unwrap();
+
+        let segments = qrcode_segments_optimizer::make_segments_from_email_with_options(
+            &email,
+            ecc,
+            &profile.options(),
+        )
+        .unwrap();
+
+        QrCode::encode_segments(&segments, ecc).unwrap();
+
+        if profile == Profile::Conservative {
+            assert!(segments.len() <= 4);
+        }
+    }
+
+    #[cfg(feature = "kanji")]
+    {
+        let text = "日本語のテキスト";
+
+        let plan = qrcode_segments_optimizer::plan_segments_from_str(
+            text,
+            ecc,
+            &Profile::JapaneseDomestic.options(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![SegmentMode::Kanji],
+            plan.segments().iter().map(Segment::mode).collect::<Vec<_>>()
+        );
+
+        // Shift_JIS has no reverse solidus, and it is not put in the kanji mode either
+        assert!(matches!(
+            qrcode_segments_optimizer::plan_segments_from_str(
+                "C:\\path",
+                ecc,
+                &Profile::JapaneseDomestic.options(),
+            ),
+            Err(OptimizeError::InvalidInput)
+        ));
+
+        let plan = qrcode_segments_optimizer::plan_segments_from_str(
+            text,
+            ecc,
+            &Profile::Conservative.options(),
+        )
+        .unwrap();
+
+        assert!(!plan.segments().iter().any(|segment| segment.mode() == SegmentMode::Kanji));
+
+        // byte-mode data is in Shift_JIS without an ECI designator
+        let text = "ｶﾀｶﾅ ﾃｷｽﾄ";
+
+        let plan = qrcode_segments_optimizer::plan_segments_from_str(
+            text,
+            ecc,
+            &Profile::JapaneseDomestic.options(),
+        )
+        .unwrap();
+
+        assert!(eci_assignments(plan.segments()).is_empty());
+        assert_eq!(
+            vec![(SegmentMode::Byte, text.chars().count())],
+            plan.segments()
+                .iter()
+                .map(|segment| (segment.mode(), segment.num_chars()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Ok(text.to_string()),
+            qrcode_segments_optimizer::decode_segments_with_implicit_charset(
+                plan.segments(),
+                Charset::ShiftJis
+            )
+        );
+    }
+
+    #[cfg(not(feature = "kanji"))]
+    assert_eq!(Charset::Utf8, Profile::JapaneseDomestic.options().implicit_charset);
+}
+
+#[test]
+fn data_codewords() {
+    let url = Url::parse("https://magiclen.org/path/to/12345").unwrap();
+    let ecc = QrCodeEcc::Quartile;
+
+    let plan =
+        qrcode_segments_optimizer::plan_segments_from_url(&url, ecc, &Options::new()).unwrap();
+
+    let qr =
+        QrCode::encode_codewords(plan.version().unwrap(), ecc, &plan.to_data_codewords(), None);
+
+    let expected_qr = QrCode::encode_segments_advanced(
+        &plan.to_qr_segments().unwrap(),
+        ecc,
+        plan.version().unwrap(),
+        plan.version().unwrap(),
+        None,
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(expected_qr.size(), qr.size());
+
+    for y in 0..qr.size() {
+        for x in 0..qr.size() {
+            assert_eq!(expected_qr.get_module(x, y), qr.get_module(x, y));
+        }
+    }
+}
+
+#[test]
+fn segment_constructors() {
+    let bytes = "Hello, 世界!".as_bytes();
+
+    assert_eq!(Segment::from(QrSegment::make_bytes(bytes)), Segment::make_bytes(bytes));
+
+    for text in ["", "0", "01", "012", "0123456789", "9876543210987"] {
+        assert_eq!(Segment::from(QrSegment::make_numeric(text)), Segment::make_numeric(text));
+    }
+
+    for text in ["", "A", "AB", "HELLO WORLD", "$%*+-./:0123456789"] {
+        assert_eq!(
+            Segment::from(QrSegment::make_alphanumeric(text)),
+            Segment::make_alphanumeric(text)
+        );
+    }
+
+    for assign_val in [0, 3, 26, 127, 128, 16383, 16384, 999_999] {
+        assert_eq!(Segment::from(QrSegment::make_eci(assign_val)), Segment::make_eci(assign_val));
+    }
+}
+
+#[test]
+fn boost_ecc() {
+    let text = "HELLO WORLD";
+
+    let plan =
+        qrcode_segments_optimizer::plan_segments_from_str(text, QrCodeEcc::Low, &Options::new())
+            .unwrap();
+
+    assert_eq!(QrCodeEcc::Low, plan.ecc());
+
+    let plan = qrcode_segments_optimizer::plan_segments_from_str(
+        text,
+        QrCodeEcc::Low,
+        &Options::new().boost_ecc(true),
+    )
+    .unwrap();
+
+    // 74 bits fit the 104 bits of Quartile, but not the 72 bits of High
+    assert_eq!(QrCodeEcc::Quartile, plan.ecc());
+    assert_eq!(Some(Version::new(1)), plan.version());
+    assert_eq!(104, plan.capacity_bits());
+
+    let qr = QrCode::encode_segments_advanced(
+        &plan.to_qr_segments().unwrap(),
+        QrCodeEcc::Low,
+        Version::MIN,
+        Version::MAX,
+        None,
+        true,
+    )
+    .unwrap();
+
+    assert_eq!(qr.error_correction_level(), plan.ecc());
+}
+
+#[test]
+fn ecc_levels() {
+    let text = "HELLO WORLD";
+
+    let report =
+        qrcode_segments_optimizer::plan_ecc_levels_from_str(text, &Options::new()).unwrap();
+
+    for (ecc, plan) in report.plans() {
+        let plan = plan.unwrap();
+
+        let expected_plan =
+            qrcode_segments_optimizer::plan_segments_from_str(text, ecc, &Options::new()).unwrap();
+
+        assert_eq!(ecc, plan.ecc());
+        assert_eq!(expected_plan.version(), plan.version());
+        assert_eq!(expected_plan.spare_bits(), plan.spare_bits());
+    }
+
+    // version 1 holds the text up to Quartile, and version 2 is needed for High
+    let pareto: Vec<_> = report
+        .pareto()
+        .into_iter()
+        .map(|plan| (plan.version().unwrap().value(), plan.ecc()))
+        .collect();
+
+    assert_eq!(vec![(1, QrCodeEcc::Quartile), (2, QrCodeEcc::High)], pareto);
+
+    let report = qrcode_segments_optimizer::plan_ecc_levels_from_str(
+        text,
+        &Options::new().version_range(Version::new(1), Version::new(1)),
+    )
+    .unwrap();
+
+    assert!(report.plan(QrCodeEcc::Quartile).is_some());
+    assert!(report.plan(QrCodeEcc::High).is_none());
+
+    assert_eq!(
+        OptimizeError::InvalidInput,
+        qrcode_segments_optimizer::plan_ecc_levels_from_str(
+            text,
+            &Options::new().modes(SegmentModes::NUMERIC)
+        )
+        .unwrap_err()
+    );
+}
+
+#[test]
+fn fixed_symbol() {
+    let url = "https://magiclen.org/path/to/12345";
+    let ecc = QrCodeEcc::Low;
+    let symbol = SymbolVersion::Qr(Version::new(3));
+
+    let plan = qrcode_segments_optimizer::plan_segments_from_str_for_symbol(
+        url,
+        symbol,
+        ecc,
+        &Options::new(),
+    )
+    .unwrap();
+
+    assert_eq!(symbol, plan.symbol());
+
+    assert!(matches!(
+        qrcode_segments_optimizer::plan_segments_from_str_for_symbol(
+            url,
+            SymbolVersion::Qr(Version::new(2)),
+            ecc,
+            &Options::new(),
+        ),
+        Err(OptimizeError::DataTooLong {
+            capacity_bits: 272,
+            ..
+        })
+    ));
+
+    let long_url = url.repeat(2);
+
+    let (prefix, plan) =
+        qrcode_segments_optimizer::truncate_str_to_fit(&long_url, symbol, ecc, &Options::new())
+            .unwrap();
+
+    assert!(long_url.starts_with(prefix));
+    assert!(prefix.len() >= url.len() && prefix.len() < long_url.len());
+    assert!(plan.data_bits() <= plan.capacity_bits());
+
+    // one more character does not fit
+    let next_len = prefix.len() + 1;
+
+    assert!(qrcode_segments_optimizer::plan_segments_from_str_for_symbol(
+        &long_url[..next_len],
+        symbol,
+        ecc,
+        &Options::new(),
+    )
+    .is_err());
+
+    // an accent is not separated from its base character, nor a flag from its other half
+    let text = "e\u{301}\u{1F1EF}\u{1F1F5}".repeat(30);
+
+    let (prefix, _) = qrcode_segments_optimizer::truncate_str_to_fit(
+        &text,
+        SymbolVersion::Qr(Version::new(1)),
+        ecc,
+        &Options::new(),
+    )
+    .unwrap();
+
+    assert!(!prefix.is_empty());
+    assert_eq!(0, prefix.chars().count() % 2);
+
+    // nor a vowel sign of an Indic or Thai script from its consonant
+    for cluster in ["கு", "কি", "ਕਿ", "กำ"] {
+        let text = cluster.repeat(30);
+
+        let (prefix, _) = qrcode_segments_optimizer::truncate_str_to_fit(
+            &text,
+            SymbolVersion::Qr(Version::new(1)),
+            ecc,
+            &Options::new(),
+        )
+        .unwrap();
+
+        assert!(!prefix.is_empty());
+        assert_eq!(0, prefix.len() % cluster.len(), "{:?}", prefix);
+    }
+
+    let (prefix, plan) = qrcode_segments_optimizer::truncate_bytes_to_fit(
+        &[0xFF; 100],
+        SymbolVersion::Qr(Version::new(1)),
+        ecc,
+        &Options::new(),
+    )
+    .unwrap();
+
+    assert_eq!(17, prefix.len());
+    assert_eq!(4 + 8 + 17 * 8, plan.data_bits());
+
+    // under a minimum run length, a longer prefix can need fewer bits than a shorter one: the digits after "a" have to be in the byte mode and do not fit until there are 20 of them, which can be in the numeric mode
+    let options = Options::new()
+        .modes(SegmentModes::BYTE.with(SegmentMode::Numeric))
+        .cost_model(CostModel::new().with_min_run_length(SegmentMode::Numeric, 20));
+
+    let text = format!("a{}", "1".repeat(36));
+
+    let (prefix, plan) = qrcode_segments_optimizer::truncate_str_to_fit(
+        &text,
+        SymbolVersion::Qr(Version::new(1)),
+        ecc,
+        &options,
+    )
+    .unwrap();
+
+    assert_eq!(1 + 35, prefix.len());
+    assert_eq!((4 + 8 + 8) + (4 + 10 + 11 * 10 + 7), plan.data_bits());
+}
+
+#[test]
+fn micro() {
+    let options = Options::new().target(Target::MicroOrQr);
+
+    let plan = qrcode_segments_optimizer::plan_segments_from_str("12345", QrCodeEcc::Low, &options)
+        .unwrap();
+
+    assert_eq!(SymbolVersion::Micro(MicroVersion::new(1)), plan.symbol());
+    assert_eq!(None, plan.version());
+    assert_eq!(None, plan.naive_bits());
+    assert_eq!(0, plan.spare_bits());
+    // 3-bit length + 10 bits for "123" + 7 bits for "45", and the last codeword is 4 bits long
+    assert_eq!(vec![0b1010_0011, 0b1101_1010, 0b1101_0000], plan.to_data_codewords());
+
+    let plan = qrcode_segments_optimizer::plan_segments_from_str("HELLO", QrCodeEcc::Low, &options)
+        .unwrap();
+
+    assert_eq!(SymbolVersion::Micro(MicroVersion::new(2)), plan.symbol());
+
+    let plan =
+        qrcode_segments_optimizer::plan_segments_from_str("hello", QrCodeEcc::Medium, &options)
+            .unwrap();
+
+    assert_eq!(SymbolVersion::Micro(MicroVersion::new(3)), plan.symbol());
+    assert_eq!(68 / 8 + 1, plan.to_data_codewords().len());
+
+    // Micro QR code symbols support neither the High level nor ECI designators
+    let plan =
+        qrcode_segments_optimizer::plan_segments_from_str("12345", QrCodeEcc::High, &options)
+            .unwrap();
+
+    assert_eq!(Some(Version::new(1)), plan.version());
+
+    let plan = qrcode_segments_optimizer::plan_segments_from_str(
+        "12345",
+        QrCodeEcc::Low,
+        &options.eci(EciPolicy::Prefix),
+    )
+    .unwrap();
+
+    assert_eq!(Some(Version::new(1)), plan.version());
+
+    // the functions which return `QrSegment`s only choose QR code symbols
+    let segments = qrcode_segments_optimizer::make_segments_from_str_with_options(
+        "12345",
+        QrCodeEcc::Low,
+        &options,
+    )
+    .unwrap();
+
+    assert_eq!(1, QrCode::encode_segments(&segments, QrCodeEcc::Low).unwrap().version().value());
+}
+
+#[test]
+fn optimize_errors() {
+    let m1 = SymbolVersion::Micro(MicroVersion::new(1));
+    let options = Options::new();
+
+    assert_eq!(
+        Some(OptimizeError::SegmentTooLong {
+            mode:      SegmentMode::Numeric,
+            num_chars: 8,
+            max_chars: 7,
+        }),
+        qrcode_segments_optimizer::plan_segments_from_str_for_symbol(
+            "12345678",
+            m1,
+            QrCodeEcc::Low,
+            &options
+        )
+        .err()
+    );
+
+    assert_eq!(
+        Some(OptimizeError::InvalidInput),
+        qrcode_segments_optimizer::plan_segments_from_str_for_symbol(
+            "ABC",
+            m1,
+            QrCodeEcc::Low,
+            &options
+        )
+        .err()
+    );
+
+    assert_eq!(
+        Some(OptimizeError::UnsupportedEcc {
+            symbol: m1, ecc: QrCodeEcc::High
+        }),
+        qrcode_segments_optimizer::plan_segments_from_str_for_symbol(
+            "123",
+            m1,
+            QrCodeEcc::High,
+            &options
+        )
+        .err()
+    );
+
+    // data which does not fit even without the constraints of the cost model is rejected before the constrained optimization
+    let cost_model =
+        CostModel::new().max_segments(Some(100_000)).with_min_run_length(SegmentMode::Numeric, 50);
+
+    assert!(matches!(
+        qrcode_segments_optimizer::plan_segments_from_str(
+            "a1".repeat(100_000),
+            QrCodeEcc::Low,
+            &options.cost_model(cost_model)
+        ),
+        Err(OptimizeError::DataTooLong { .. })
+    ));
+
+    // the errors still convert into `QRCodeError`
+    assert!(matches!(
+        qrcode_segments_optimizer::make_segments_from_str("1".repeat(8000), QrCodeEcc::Low),
+        Err(qrcode_generator::QRCodeError::DataTooLong)
+    ));
+
+    match qrcode_generator::QRCodeError::from(OptimizeError::InvalidInput) {
+        qrcode_generator::QRCodeError::IOError(error) => {
+            assert_eq!(std::io::ErrorKind::InvalidInput, error.kind())
+        },
+        _ => panic!("InvalidInput is not converted into an I/O error"),
+    }
+
+    match qrcode_generator::QRCodeError::from(OptimizeError::OutOfMemory) {
+        qrcode_generator::QRCodeError::IOError(error) => {
+            assert_eq!(std::io::ErrorKind::OutOfMemory, error.kind())
+        },
+        _ => panic!("OutOfMemory is not converted into an I/O error"),
+    }
+}
+
+#[test]
+fn empty_input() {
+    let ecc = QrCodeEcc::Low;
+
+    assert!(qrcode_segments_optimizer::make_segments_from_str("", ecc).unwrap().is_empty());
+    assert!(qrcode_segments_optimizer::make_segments_from_bytes([], ecc).unwrap().is_empty());
+
+    let plan = qrcode_segments_optimizer::plan_segments_from_str(
+        "",
+        ecc,
+        &Options::new().target(Target::MicroOrQr),
+    )
+    .unwrap();
+
+    assert_eq!(SymbolVersion::Micro(MicroVersion::new(1)), plan.symbol());
+    assert_eq!(0, plan.data_bits());
+
+    let plans =
+        qrcode_segments_optimizer::plan_structured_append_from_str("", ecc, &Options::new())
+            .unwrap();
+
+    assert_eq!(1, plans.len());
+
+    // a file URL has no host
+    let url = Url::parse("file:///etc/hosts").unwrap();
+
+    qrcode_segments_optimizer::make_segments_from_url(&url, ecc).unwrap();
+}
+
+#[test]
+fn rmqr() {
+    let options = Options::new().target(Target::Rmqr);
+
+    let plan =
+        qrcode_segments_optimizer::plan_segments_from_str("12345", QrCodeEcc::Medium, &options)
+            .unwrap();
+
+    // R11x27 has a smaller area than R7x43
+    assert_eq!(SymbolVersion::Rmqr(RmqrVersion::new(11, 27)), plan.symbol());
+    assert_eq!("R11x27", plan.symbol().to_string());
+    assert_eq!(7 * 8, plan.capacity_bits());
+    // 3-bit mode indicator + 4-bit length + 17 bits for the digits
+    assert_eq!(3 + 4 + 17, plan.data_bits());
+    assert_eq!(Some(3 + 3 + 5 * 8), plan.naive_bits());
+
+    let data_codewords = plan.to_data_codewords();
+
+    assert_eq!(7, data_codewords.len());
+    // the mode indicator 001 and the length 0101
+    assert_eq!(0b0010_1010, data_codewords[0] & 0b1111_1110);
+    assert_eq!([0xEC, 0x11, 0xEC], data_codewords[4..]);
+
+    let url = Url::parse("https://magiclen.org/path/to/12345").unwrap();
+
+    let plan =
+        qrcode_segments_optimizer::plan_segments_from_url(&url, QrCodeEcc::Quartile, &options)
+            .unwrap();
+
+    let version = plan.symbol().rmqr_version().unwrap();
+
+    assert!(plan.data_bits() <= plan.capacity_bits());
+    assert!(version.height() < version.width());
+    // the Quartile level is raised to H, which needs a larger symbol than M does
+    let medium_plan =
+        qrcode_segments_optimizer::plan_segments_from_url(&url, QrCodeEcc::Medium, &options)
+            .unwrap();
+
+    assert!(
+        medium_plan.symbol().width() * medium_plan.symbol().height()
+            <= plan.symbol().width() * plan.symbol().height()
+    );
+
+    // the 8-bit character count field of the byte mode in R17x139 holds a run of 148 bytes
+    let r17x139 = SymbolVersion::Rmqr(RmqrVersion::new(17, 139));
+    let text = "a".repeat(148);
+
+    let plan = qrcode_segments_optimizer::plan_segments_from_str_for_symbol(
+        &text,
+        r17x139,
+        QrCodeEcc::Medium,
+        &options,
+    )
+    .unwrap();
+
+    assert_eq!(1, plan.segments().len());
+    assert_eq!(SegmentMode::Byte, plan.segments()[0].mode());
+    assert_eq!(148, plan.segments()[0].num_chars());
+    assert_eq!(3 + 8 + 148 * 8, plan.data_bits());
+    assert_eq!(Ok(text), qrcode_segments_optimizer::decode_segments(plan.segments()));
+}
+
+#[test]
+fn structured_append() {
+    let text = "SSID=MAGICLEN-OFFICE;PSK=0123456789012345678901234567890123456789;\
+                NTP=pool.ntp.org;LOG=https://magiclen.org/logs/2024/device/1234567890";
+    let ecc = QrCodeEcc::Medium;
+
+    let options = Options::new().version_range(Version::new(1), Version::new(3));
+
+    assert!(matches!(
+        qrcode_segments_optimizer::plan_segments_from_str(text, ecc, &options),
+        Err(OptimizeError::DataTooLong { .. })
+    ));
+
+    let plans =
+        qrcode_segments_optimizer::plan_structured_append_from_str(text, ecc, &options).unwrap();
+
+    assert!(plans.len() > 1 && plans.len() <= 16);
+
+    let parity = text.bytes().fold(0, |parity, b| parity ^ b);
+    let version = plans[0].version().unwrap();
+
+    for (index, plan) in plans.iter().enumerate() {
+        assert_eq!(Some(version), plan.version());
+        assert!(plan.data_bits() <= plan.capacity_bits());
+
+        let header = &plan.segments()[0];
+
+        assert_eq!(SegmentMode::StructuredAppend, header.mode());
+        assert_eq!(
+            Segment::make_structured_append(index as u8, plans.len() as u8, parity),
+            *header
+        );
+
+        let data_codewords = plan.to_data_codewords();
+
+        // the mode indicator 0011 and the 4-bit index
+        assert_eq!(0x30 | index as u8, data_codewords[0]);
+
+        QrCode::encode_codewords(version, ecc, &data_codewords, None);
+    }
+
+    // the last symbol has room to spare, but the level is only raised as far as every symbol can be
+    let boosted_plans = qrcode_segments_optimizer::plan_structured_append_from_str(
+        text,
+        ecc,
+        &options.boost_ecc(true),
+    )
+    .unwrap();
+
+    assert_eq!(plans.len(), boosted_plans.len());
+
+    for plan in boosted_plans.iter() {
+        assert_eq!(ecc, plan.ecc());
+        assert!(plan.data_bits() <= plan.capacity_bits());
+    }
+
+    // the parity is computed over the bytes in the character set of the ECI designator, not over the UTF-8 text
+    let latin_text = "Grüße aus Köln! ".repeat(7);
+    let latin_options = options.eci(EciPolicy::Prefix).charsets(Charsets::ISO_8859_1);
+
+    let latin_plans = qrcode_segments_optimizer::plan_structured_append_from_str(
+        &latin_text,
+        ecc,
+        &latin_options,
+    )
+    .unwrap();
+
+    assert!(latin_plans.len() > 1);
+
+    let latin_parity = latin_text
+        .chars()
+        .map(|c| u8::try_from(u32::from(c)).unwrap())
+        .fold(0, |parity, b| parity ^ b);
+
+    assert_ne!(latin_text.bytes().fold(0, |parity, b| parity ^ b), latin_parity);
+
+    for (index, plan) in latin_plans.iter().enumerate() {
+        assert_eq!(
+            Segment::make_structured_append(index as u8, latin_plans.len() as u8, latin_parity),
+            plan.segments()[0]
+        );
+    }
+
+    // a smaller version needs more symbols
+    let plans_v1 = qrcode_segments_optimizer::plan_structured_append_from_str(
+        text,
+        ecc,
+        &Options::new().version_range(Version::new(1), Version::new(1)),
+    )
+    .unwrap();
+
+    assert!(plans_v1.len() >= plans.len());
+
+    match qrcode_segments_optimizer::plan_structured_append_from_str(
+        text.repeat(8),
+        ecc,
+        &Options::new().version_range(Version::new(1), Version::new(1)),
+    ) {
+        Err(OptimizeError::DataTooLong {
+            required_bits,
+            capacity_bits,
+        }) => {
+            assert_eq!(16 * 128, capacity_bits);
+            assert!(required_bits > capacity_bits);
+        },
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn decode() {
+    let mut texts = vec![
+        "",
+        "0123456789",
+        "HELLO WORLD 12345",
+        "https://magiclen.org/path/to/12345?q=ABCDEFG",
+        "Grüße aus Köln, 1234567890",
+        "ｶﾀｶﾅ ﾃｷｽﾄ ﾃﾞｽ",
+        "QR コード 2024",
+    ];
+
+    if cfg!(feature = "hanzi") {
+        texts.push("中文二维码测试，汉字模式 ABC 123");
+    }
+
+    let options = Options::new().charsets(Charsets::all());
+
+    for text in texts {
+        for options in [
+            options,
+            options.eci(EciPolicy::BeforeNonAscii),
+            options.eci(EciPolicy::Prefix),
+            options.target(Target::MicroOrQr),
+            options.target(Target::Rmqr),
+        ] {
+            let plan =
+                qrcode_segments_optimizer::plan_segments_from_str(text, QrCodeEcc::Low, &options)
+                    .unwrap();
+
+            assert_eq!(
+                Ok(text.to_string()),
+                qrcode_segments_optimizer::decode_segments(plan.segments())
+            );
+
+            let segments = qrcode_segments_optimizer::parse_data_codewords(
+                &plan.to_data_codewords(),
+                plan.symbol(),
+            )
+            .unwrap();
+
+            assert_eq!(plan.segments(), segments.as_slice());
+        }
+    }
+
+    let data = [0u8, 1, 2, 3, 0xFF, b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8'];
+
+    let segments =
+        qrcode_segments_optimizer::make_segments_from_bytes(data, QrCodeEcc::Low).unwrap();
+    let segments = segments.into_iter().map(Segment::from).collect::<Vec<Segment>>();
+
+    assert_eq!(Ok(data.to_vec()), qrcode_segments_optimizer::decode_segments_to_bytes(&segments));
+    assert_eq!(
+        Err(DecodeError::InvalidEncoding {
+            charset: Charset::Utf8
+        }),
+        qrcode_segments_optimizer::decode_segments(&segments)
+    );
+
+    // the error reports the implicit character set which the data was decoded in
+    #[cfg(feature = "kanji")]
+    assert_eq!(
+        Err(DecodeError::InvalidEncoding {
+            charset: Charset::ShiftJis
+        }),
+        qrcode_segments_optimizer::decode_segments_with_implicit_charset(
+            &segments,
+            Charset::ShiftJis
+        )
+    );
+
+    // the mode indicator 0110 is not used
+    assert_eq!(
+        Err(DecodeError::InvalidModeIndicator {
+            indicator: 0b0110
+        }),
+        qrcode_segments_optimizer::parse_data_codewords(
+            &[0x60],
+            SymbolVersion::Qr(Version::new(1))
+        )
+    );
+
+    // a Hanzi segment of the ideographic space, which is only decoded to text with the hanzi feature
+    let segments = qrcode_segments_optimizer::parse_data_codewords(
+        &[0xD1, 0x01, 0x00, 0x00],
+        SymbolVersion::Qr(Version::new(1)),
+    )
+    .unwrap();
+
+    assert_eq!(
+        Ok(vec![0xA1, 0xA1]),
+        qrcode_segments_optimizer::decode_segments_to_bytes(&segments)
+    );
+    assert_eq!(
+        if cfg!(feature = "hanzi") {
+            Ok("\u{3000}".to_string())
+        } else {
+            Err(DecodeError::UnsupportedMode {
+                mode: SegmentMode::Hanzi
+            })
+        },
+        qrcode_segments_optimizer::decode_segments(&segments)
+    );
+
+    // a numeric segment of 5 digits whose data is cut off
+    assert_eq!(
+        Err(DecodeError::UnexpectedEnd),
+        qrcode_segments_optimizer::parse_data_codewords(
+            &[0x10, 0x14, 0x00],
+            SymbolVersion::Qr(Version::new(1))
+        )
+    );
+}
+
+#[test]
+fn strategies() {
+    let ecc = QrCodeEcc::Low;
+    let annex_j = Options::new().strategy(Strategy::AnnexJ);
+
+    let plan_modes = |text: &str, version: u8, options: &Options| {
+        qrcode_segments_optimizer::plan_segments_from_str_for_symbol(
+            text,
+            SymbolVersion::Qr(Version::new(version)),
+            ecc,
+            options,
+        )
+        .unwrap()
+        .segments()
+        .iter()
+        .map(Segment::mode)
+        .collect::<Vec<SegmentMode>>()
+    };
+
+    // a short numeric run which is followed by alphanumeric characters starts in the alphanumeric mode
+    assert_eq!(vec![SegmentMode::Alphanumeric], plan_modes("123ABC", 1, &annex_j));
+
+    // the byte mode switches to the numeric mode for a run of 6 digits in versions 1–9, but of 8 digits in versions 10–26
+    assert_eq!(
+        vec![SegmentMode::Byte, SegmentMode::Numeric, SegmentMode::Byte],
+        plan_modes("a123456b", 1, &annex_j)
+    );
+    assert_eq!(vec![SegmentMode::Byte], plan_modes("a123456b", 10, &annex_j));
+    assert_eq!(
+        vec![SegmentMode::Byte, SegmentMode::Numeric, SegmentMode::Byte],
+        plan_modes("a12345678b", 10, &annex_j)
+    );
+
+    // the cost model is ignored
+    assert_eq!(
+        vec![SegmentMode::Byte, SegmentMode::Numeric, SegmentMode::Byte],
+        plan_modes(
+            "a123456b",
+            1,
+            &annex_j.cost_model(CostModel::new().segment_penalty(100).max_segments(Some(1)))
+        )
+    );
+
+    // the thresholds do not look at the end of the data
+    let comparison =
+        qrcode_segments_optimizer::compare_strategies_from_str("a62026", ecc, &Options::new())
+            .unwrap();
+
+    assert_eq!(
+        vec![SegmentMode::Byte, SegmentMode::Numeric],
+        comparison.optimal().segments().iter().map(Segment::mode).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        vec![SegmentMode::Byte],
+        comparison.annex_j().segments().iter().map(Segment::mode).collect::<Vec<_>>()
+    );
+    assert_eq!(9, comparison.bit_difference());
+    assert!(!comparison.is_identical());
+
+    let comparison = qrcode_segments_optimizer::compare_strategies_from_bytes(
+        b"a1234567b",
+        ecc,
+        &Options::new(),
+    )
+    .unwrap();
+
+    assert_eq!(0, comparison.bit_difference());
+    assert!(comparison.is_identical());
+
+    // ECI designators are still placed optimally
+    let options = Options::new().eci(EciPolicy::BeforeNonAscii);
+    let comparison =
+        qrcode_segments_optimizer::compare_strategies_from_str("café", ecc, &options).unwrap();
+
+    assert_eq!(
+        eci_assignments(comparison.optimal().segments()),
+        eci_assignments(comparison.annex_j().segments())
+    );
+
+    #[cfg(feature = "kanji")]
+    assert_eq!(vec![SegmentMode::Kanji], plan_modes("日本語の文章", 1, &annex_j));
+
+    // Annex J does not define the Hanzi mode
+    #[cfg(feature = "hanzi")]
+    assert!(!plan_modes("中文文本", 1, &annex_j).contains(&SegmentMode::Hanzi));
+
+    let alphabet = "0123456789ABCZ $:/.abcz@#\\éß€".chars().collect::<Vec<char>>();
+
+    let mut random = xorshift(0x2545_F491_4F6C_DD1Du64);
+
+    for _ in 0..300 {
+        let len = 1 + random(40);
+        let text = (0..len).map(|_| alphabet[random(alphabet.len())]).collect::<String>();
+
+        let comparison =
+            qrcode_segments_optimizer::compare_strategies_from_str(&text, ecc, &Options::new())
+                .unwrap();
+
+        assert!(comparison.bit_difference() >= 0, "{:?}", text);
+    }
+}
+
+#[test]
+fn constraints() {
+    let ecc = QrCodeEcc::Low;
+    let options = Options::new();
+
+    let plan_segments = |text: &str, options: &Options, constraints: &SegmentConstraints| {
+        qrcode_segments_optimizer::plan_segments_from_str_with_constraints(
+            text,
+            ecc,
+            options,
+            constraints,
+        )
+        .map(|plan| {
+            plan.segments()
+                .iter()
+                .map(|segment| (segment.mode(), segment.num_chars()))
+                .collect::<Vec<(SegmentMode, usize)>>()
+        })
+    };
+
+    let text = "ORDER 12345678901234 SHIPPED";
+
+    assert_eq!(
+        Ok(vec![
+            (SegmentMode::Alphanumeric, 6),
+            (SegmentMode::Numeric, 14),
+            (SegmentMode::Alphanumeric, 8)
+        ]),
+        plan_segments(text, &options, &SegmentConstraints::new())
+    );
+
+    // the serial stays in a byte-mode segment of its own, and the rest is still optimized
+    assert_eq!(
+        Ok(vec![
+            (SegmentMode::Alphanumeric, 6),
+            (SegmentMode::Byte, 14),
+            (SegmentMode::Alphanumeric, 8)
+        ]),
+        plan_segments(text, &options, &SegmentConstraints::new().pin(6..20, SegmentMode::Byte))
+    );
+
+    // a pinned range is a segment of its own even in the mode of its neighbours
+    assert_eq!(
+        Ok(vec![
+            (SegmentMode::Alphanumeric, 6),
+            (SegmentMode::Alphanumeric, 5),
+            (SegmentMode::Numeric, 9),
+            (SegmentMode::Alphanumeric, 8)
+        ]),
+        plan_segments(
+            text,
+            &options,
+            &SegmentConstraints::new().pin(6..11, SegmentMode::Alphanumeric)
+        )
+    );
+
+    assert_eq!(
+        Ok(vec![(SegmentMode::Numeric, 10), (SegmentMode::Numeric, 10)]),
+        plan_segments("12345678901234567890", &options, &SegmentConstraints::new().break_at(10))
+    );
+
+    // the start and the end of the data are always boundaries
+    assert_eq!(
+        Ok(vec![(SegmentMode::Numeric, 20)]),
+        plan_segments(
+            "12345678901234567890",
+            &options,
+            &SegmentConstraints::new().break_at(0).break_at(20)
+        )
+    );
+
+    // pinned segments and segments ending at forced boundaries are exempt from the minimum run lengths
+    let cost_model = CostModel::new().with_min_run_length(SegmentMode::Numeric, 12);
+
+    assert_eq!(
+        Ok(vec![(SegmentMode::Numeric, 4), (SegmentMode::Numeric, 4), (SegmentMode::Byte, 1)]),
+        plan_segments(
+            "12345678a",
+            &options.cost_model(cost_model),
+            &SegmentConstraints::new().break_at(4).pin(4..8, SegmentMode::Numeric)
+        )
+    );
+
+    assert_eq!(
+        Ok(vec![(SegmentMode::Byte, 6)]),
+        plan_segments(
+            "123456",
+            &options.strategy(Strategy::AnnexJ),
+            &SegmentConstraints::new().pin(0..6, SegmentMode::Byte)
+        )
+    );
+
+    for constraints in [
+        // not at a character boundary
+        SegmentConstraints::new().break_at(1),
+        SegmentConstraints::new().pin(0..30, SegmentMode::Byte),
+        // overlapping
+        SegmentConstraints::new().pin(0..8, SegmentMode::Byte).pin(6..10, SegmentMode::Byte),
+        // inside a pinned range
+        SegmentConstraints::new().pin(0..8, SegmentMode::Byte).break_at(4),
+        // not a mode of data
+        SegmentConstraints::new().pin(0..4, SegmentMode::Eci),
+        // not able to encode the range
+        SegmentConstraints::new().pin(0..8, SegmentMode::Numeric),
+    ] {
+        assert_eq!(
+            Err(OptimizeError::InvalidInput),
+            plan_segments("é ORDER 12345678901234", &options, &constraints)
+        );
+    }
+
+    assert_eq!(
+        Err(OptimizeError::InvalidInput),
+        plan_segments(
+            "12345678901234567890",
+            &options.cost_model(CostModel::new().max_segments(Some(1))),
+            &SegmentConstraints::new().break_at(10)
+        )
+    );
+
+    let alphabet = "0123456789ABCZ $:/.abcz@#\\éß€".chars().collect::<Vec<char>>();
+
+    let mut random = xorshift(0x2545_F491_4F6C_DD1Du64);
+
+    // a forced boundary splits the data into two parts which are optimized on their own
+    for _ in 0..300 {
+        let len = 2 + random(20);
+        let text = (0..len).map(|_| alphabet[random(alphabet.len())]).collect::<String>();
+        let offset = text.char_indices().nth(1 + random(len - 1)).unwrap().0;
+
+        let symbol = SymbolVersion::Qr(Version::new(10));
+        let options = Options::new().version_range(Version::new(10), Version::new(10));
+
+        let bits = |text: &str| {
+            qrcode_segments_optimizer::plan_segments_from_str_for_symbol(
+                text, symbol, ecc, &options,
+            )
+            .unwrap()
+            .data_bits()
+        };
+
+        let plan = qrcode_segments_optimizer::plan_segments_from_str_with_constraints(
+            &text,
+            ecc,
+            &options,
+            &SegmentConstraints::new().break_at(offset),
+        )
+        .unwrap();
+
+        assert_eq!(
+            bits(&text[..offset]) + bits(&text[offset..]),
+            plan.data_bits(),
+            "{:?} at {}",
+            text,
+            offset
+        );
+    }
+}
+
+#[test]
+fn marked_text() {
+    let ecc = QrCodeEcc::Low;
+    let text = "your order ab12cd34ef56gh78ij90 is ok, thanks";
+
+    let plain_plan =
+        qrcode_segments_optimizer::plan_segments_from_str(text, ecc, &Options::new()).unwrap();
+
+    // the order code is worth uppercasing, but the short word is not
+    let marked_text = MarkedText::new(text).case_insensitive(35..37).case_insensitive(11..31);
+
+    let plan = qrcode_segments_optimizer::plan_segments_from_marked_text(
+        &marked_text,
+        ecc,
+        &Options::new(),
+    )
+    .unwrap();
+
+    assert_eq!(Some(&(11..31)), plan.rewritten_spans().first());
+    assert_eq!(1, plan.rewritten_spans().len());
+    assert_eq!("your order AB12CD34EF56GH78IJ90 is ok, thanks", plan.text());
+    assert!(plan.plan().data_bits() < plain_plan.data_bits());
+    assert!(plan
+        .plan()
+        .segments()
+        .iter()
+        .any(|segment| segment.mode() == SegmentMode::Alphanumeric && segment.num_chars() >= 20));
+    assert_eq!(
+        plan.text(),
+        qrcode_segments_optimizer::decode_segments(plan.plan().segments()).unwrap()
+    );
+
+    let plan = qrcode_segments_optimizer::plan_segments_from_marked_text(
+        &MarkedText::new(text).case_insensitive(35..37),
+        ecc,
+        &Options::new(),
+    )
+    .unwrap();
+
+    assert!(plan.rewritten_spans().is_empty());
+    assert_eq!(text, plan.text());
+    assert_eq!(plain_plan.data_bits(), plan.plan().data_bits());
+
+    // the spans are restored greedily in the order of the text, not chosen jointly: with Annex J, restoring "b3" alone costs bits, so it is kept uppercased,
+    // although restoring "4c3" afterward makes the plan as short as the one without rewritten spans
+    let text = "c-2b3344244c3x.3";
+    let options = Options::new().strategy(Strategy::AnnexJ);
+
+    let plan = qrcode_segments_optimizer::plan_segments_from_marked_text(
+        &MarkedText::new(text).case_insensitive(3..5).case_insensitive(9..12),
+        ecc,
+        &options,
+    )
+    .unwrap();
+
+    assert_eq!(Some(&(3..5)), plan.rewritten_spans().first());
+    assert_eq!(1, plan.rewritten_spans().len());
+    assert_eq!("c-2B3344244c3x.3", plan.text());
+    assert_eq!(
+        qrcode_segments_optimizer::plan_segments_from_str(text, ecc, &options).unwrap().data_bits(),
+        plan.plan().data_bits()
+    );
+
+    // with the optimal strategy, the plan is as short as the one where every span is uppercased
+    let text = "order ab12 cd34 ref 5678ef";
+    let marked_text = MarkedText::new(text)
+        .case_insensitive(6..10)
+        .case_insensitive(11..15)
+        .case_insensitive(20..26);
+
+    let plan = qrcode_segments_optimizer::plan_segments_from_marked_text(
+        &marked_text,
+        ecc,
+        &Options::new(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        qrcode_segments_optimizer::plan_segments_from_str(
+            "order AB12 CD34 ref 5678EF",
+            ecc,
+            &Options::new()
+        )
+        .unwrap()
+        .data_bits(),
+        plan.plan().data_bits()
+    );
+
+    for marked_text in [
+        MarkedText::new(text).case_insensitive(11..31).case_insensitive(30..37),
+        MarkedText::new(text).case_insensitive(11..50),
+        MarkedText::new("café").case_insensitive(0..4),
+    ] {
+        assert_eq!(
+            OptimizeError::InvalidInput,
+            qrcode_segments_optimizer::plan_segments_from_marked_text(
+                &marked_text,
+                ecc,
+                &Options::new()
+            )
+            .unwrap_err()
+        );
+    }
+}
+
+#[test]
+fn optimizer() {
+    let ecc = QrCodeEcc::Medium;
+    let options = Options::new().eci(EciPolicy::BeforeNonAscii).charsets(Charsets::all());
+
+    let mut optimizer = Optimizer::new();
+
+    // the buffers grown by a long input are reused for shorter ones
+    for text in ["https://magiclen.org/".repeat(20).as_str(), "Grüße 12345", "", "ABC-123"] {
+        assert_eq!(
+            qrcode_segments_optimizer::plan_segments_from_str(text, ecc, &options)
+                .unwrap()
+                .segments(),
+            optimizer.plan_segments_from_str(text, ecc, &options).unwrap().segments()
+        );
+
+        assert_eq!(
+            qrcode_segments_optimizer::plan_segments_from_bytes(text, ecc, &options)
+                .unwrap()
+                .segments(),
+            optimizer.plan_segments_from_bytes(text.as_bytes(), ecc, &options).unwrap().segments()
+        );
+    }
+
+    let segments = optimizer.make_segments_from_str("HELLO 12345", ecc, &options).unwrap();
+
+    QrCode::encode_segments(&segments, ecc).unwrap();
+}
+
+#[cfg(feature = "kanji")]
+static UNICODE_TO_QR_KANJI: [i16; 1 << 16] =
+    include!(concat!(env!("CARGO_MANIFEST_DIR"), "/data/unicode_to_qr_kanji.json"));
+
+#[cfg(feature = "hanzi")]
+static UNICODE_TO_QR_HANZI: [i16; 1 << 16] =
+    include!(concat!(env!("CARGO_MANIFEST_DIR"), "/data/unicode_to_qr_hanzi.json"));
+
+/// Returns a xorshift generator of numbers below the given bound, so that random inputs are the same in every run.
+fn xorshift(seed: u64) -> impl FnMut(usize) -> usize {
+    let mut state = seed;
+
+    move |n: usize| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+
+        (state % n as u64) as usize
+    }
+}
+
+/// Returns the modes which can encode the character.
+fn reference_char_modes(c: char) -> Vec<SegmentMode> {
+    let mut modes = vec![SegmentMode::Byte];
+
+    if c.is_ascii_digit() {
+        modes.push(SegmentMode::Numeric);
+    }
+
+    if c.is_ascii_digit() || c.is_ascii_uppercase() || " $%*+-./:".contains(c) {
+        modes.push(SegmentMode::Alphanumeric);
+    }
+
+    #[cfg(feature = "kanji")]
+    if !c.is_ascii()
+        && (c as usize) < UNICODE_TO_QR_KANJI.len()
+        && UNICODE_TO_QR_KANJI[c as usize] != -1
+    {
+        modes.push(SegmentMode::Kanji);
+    }
+
+    #[cfg(feature = "hanzi")]
+    if (c as usize) < UNICODE_TO_QR_HANZI.len() && UNICODE_TO_QR_HANZI[c as usize] != -1 {
+        modes.push(SegmentMode::Hanzi);
+    }
+
+    modes
+}
+
+/// Returns the number of bits of the text with each character encoded in the given mode, where the runs of the same mode are merged into segments.
+fn reference_bits(chars: &[char], modes: &[SegmentMode], version: Version) -> usize {
+    let mut bits = 0;
+    let mut start = 0;
+
+    for i in 1..=chars.len() {
+        if i < chars.len() && modes[i] == modes[start] {
+            continue;
+        }
+
+        let mode = modes[start];
+        let k = i - start;
+
+        let data_bits = match mode {
+            SegmentMode::Numeric => k / 3 * 10 + [0, 4, 7][k % 3],
+            SegmentMode::Alphanumeric => k / 2 * 11 + k % 2 * 6,
+            SegmentMode::Byte => chars[start..i].iter().map(|c| c.len_utf8() * 8).sum(),
+            // the Hanzi mode also has the 4-bit subset indicator
+            _ => k * 13 + if mode == SegmentMode::Kanji { 0 } else { 4 },
+        };
+
+        bits += 4 + usize::from(mode.num_char_count_bits(version)) + data_bits;
+        start = i;
+    }
+
+    bits
+}
+
+/// Finds the fewest bits plus the penalties of the text by enumerating every assignment of modes to its characters, among the ones which satisfy the cost model.
+fn reference_optimal_cost(text: &str, version: Version, cost_model: &CostModel) -> Option<usize> {
+    let chars = text.chars().collect::<Vec<char>>();
+    let candidates = chars.iter().map(|&c| reference_char_modes(c)).collect::<Vec<_>>();
+
+    let mut indices = vec![0; chars.len()];
+    let mut best = None::<usize>;
+
+    loop {
+        let modes = indices.iter().zip(&candidates).map(|(&i, modes)| modes[i]).collect::<Vec<_>>();
+
+        // the lengths of the runs of the same mode
+        let mut runs = Vec::<(SegmentMode, usize)>::new();
+
+        for &mode in modes.iter() {
+            match runs.last_mut() {
+                Some((last, len)) if *last == mode => *len += 1,
+                _ => runs.push((mode, 1)),
+            }
+        }
+
+        let satisfied = cost_model.max_segments.map_or(true, |max| runs.len() <= max)
+            && (runs.len() == 1
+                || runs.iter().all(|&(mode, len)| len >= cost_model.min_run_length(mode)));
+
+        if satisfied {
+            let cost = reference_bits(&chars, &modes, version)
+                + cost_model.segment_penalty * (runs.len() - 1);
+
+            best = Some(best.map_or(cost, |best| best.min(cost)));
+        }
+
+        // advance to the next assignment like an odometer
+        let mut j = 0;
+
+        while j < chars.len() {
+            indices[j] += 1;
+
+            if indices[j] < candidates[j].len() {
+                break;
+            }
+
+            indices[j] = 0;
+            j += 1;
+        }
+
+        if j == chars.len() {
+            return best;
+        }
+    }
+}
+
+#[test]
+fn reference_optimizer() {
+    let mut alphabet = "0123456789ABCZ $:/.abcz@#\\éß€".chars().collect::<Vec<char>>();
+
+    if cfg!(any(feature = "kanji", feature = "hanzi")) {
+        alphabet.extend("日本中文コー、ｶ".chars());
+    }
+
+    let mut random = xorshift(0x2545_F491_4F6C_DD1Du64);
+
+    for _ in 0..300 {
+        let len = 1 + random(7);
+        let text = (0..len).map(|_| alphabet[random(alphabet.len())]).collect::<String>();
+
+        // the version classes of the character count fields: 1–9, 10–26 and 27–40
+        for version in [1, 9, 10, 26, 27, 40].map(Version::new) {
+            let plan = qrcode_segments_optimizer::plan_segments_from_str_for_symbol(
+                &text,
+                SymbolVersion::Qr(version),
+                QrCodeEcc::Low,
+                &Options::new().modes(SegmentModes::all()),
+            )
+            .unwrap();
+
+            let naive_bits =
+                4 + usize::from(SegmentMode::Byte.num_char_count_bits(version)) + text.len() * 8;
+
+            assert_eq!(Some(naive_bits), plan.naive_bits());
+            assert!(plan.data_bits() <= naive_bits, "{:?} at version {}", text, version.value());
+            assert_eq!(
+                reference_optimal_cost(&text, version, &CostModel::new()),
+                Some(plan.data_bits()),
+                "{:?} at version {}",
+                text,
+                version.value()
+            );
+        }
+    }
+}
+
+#[test]
+fn reference_cost_model() {
+    let alphabet = "0123456789ABZ :/abz@é".chars().collect::<Vec<char>>();
+
+    let mut random = xorshift(0x9E37_79B9_7F4A_7C15u64);
+
+    for _ in 0..300 {
+        let len = 1 + random(7);
+        let text = (0..len).map(|_| alphabet[random(alphabet.len())]).collect::<String>();
+
+        let cost_model = CostModel::new()
+            .segment_penalty([0, 3, 20][random(3)])
+            .max_segments([None, Some(1), Some(2), Some(3)][random(4)])
+            .with_min_run_length(SegmentMode::Numeric, 1 + random(5))
+            .with_min_run_length(SegmentMode::Alphanumeric, 1 + random(4))
+            .with_min_run_length(SegmentMode::Byte, 1 + random(3));
+
+        for version in [1, 10, 27].map(Version::new) {
+            let plan = qrcode_segments_optimizer::plan_segments_from_str_for_symbol(
+                &text,
+                SymbolVersion::Qr(version),
+                QrCodeEcc::Low,
+                &Options::new().modes(SegmentModes::all()).cost_model(cost_model),
+            );
+
+            let expected = reference_optimal_cost(&text, version, &cost_model);
+
+            let plan = match plan {
+                Ok(plan) => plan,
+                Err(error) => {
+                    assert_eq!((None, OptimizeError::InvalidInput), (expected, error));
+
+                    continue;
+                },
+            };
+
+            let segments = plan.segments();
+
+            assert!(cost_model.max_segments.map_or(true, |max| segments.len() <= max));
+            assert!(
+                segments.len() == 1
+                    || segments
+                        .iter()
+                        .all(|segment| segment.num_chars()
+                            >= cost_model.min_run_length(segment.mode()))
+            );
+            assert_eq!(
+                expected,
+                Some(plan.data_bits() + cost_model.segment_penalty * (segments.len() - 1)),
+                "{:?} at version {} with {:?}",
+                text,
+                version.value(),
+                cost_model
+            );
+        }
+    }
+
+    // a penalty large enough leaves a single segment
+    let plan = qrcode_segments_optimizer::plan_segments_from_str(
+        "HTTPS://MAGICLEN.ORG/12345678901234567890",
+        QrCodeEcc::Low,
+        &Options::new().cost_model(CostModel::new().segment_penalty(usize::MAX)),
+    )
+    .unwrap();
+
+    assert_eq!(1, plan.segments().len());
+}
+
+/// Checks the mapping of a double-byte mode in both directions against the JSON table it is generated from.
+#[cfg(any(feature = "kanji", feature = "hanzi"))]
+fn check_double_byte_table(
+    mode: SegmentMode,
+    table: &[i16; 1 << 16],
+    make: impl Fn(&str) -> Option<Segment>,
+) {
+    let mut chars = vec![None; 1 << 13];
+
+    for (c, &val) in table.iter().enumerate() {
+        let Some(c) = char::from_u32(c as u32) else {
+            continue;
+        };
+
+        let segment = make(c.encode_utf8(&mut [0; 4]));
+
+        if val >= 0 {
+            let segment = segment.unwrap_or_else(|| panic!("{:?} should be mapped", c));
+            let value = segment.data().iter().fold(0, |acc, &bit| (acc << 1) | i16::from(bit));
+
+            assert_eq!((1, val), (segment.num_chars(), value), "{:?}", c);
+
+            // if a value is mapped twice, the later character is decoded
+            chars[val as usize] = Some(c);
+        } else {
+            assert!(segment.is_none(), "{:?} should not be mapped", c);
+        }
+    }
+
+    for (val, c) in chars.into_iter().enumerate() {
+        let data = (0..13).rev().map(|i| (val >> i) & 1 == 1).collect();
+
+        let decoded = qrcode_segments_optimizer::decode_segments(&[Segment::new(mode, 1, data)]);
+
+        match c {
+            Some(c) => assert_eq!(Ok(c.to_string()), decoded),
+            None => assert_eq!(
+                Err(DecodeError::InvalidData {
+                    mode
+                }),
+                decoded
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "kanji")]
+#[test]
+fn kanji_table() {
+    check_double_byte_table(SegmentMode::Kanji, &UNICODE_TO_QR_KANJI, Segment::make_kanji);
+}
+
+#[cfg(feature = "hanzi")]
+#[test]
+fn hanzi_table() {
+    check_double_byte_table(SegmentMode::Hanzi, &UNICODE_TO_QR_HANZI, Segment::make_hanzi);
+}
+
 #[cfg(feature = "test-image")]
 #[test]
 fn url_to_png_to_file() {